@@ -0,0 +1,209 @@
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use complex::Complex;
+use config::Config;
+use projection::Rotation;
+use raw::RawError;
+use render::RenderJob;
+
+/// The view at one frame of an animation. Frames between keyframes are
+/// interpolated, with the zoom interpolated in log-space.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct Keyframe {
+    pub frame: u32,
+    pub origin: Complex,
+    pub zoom: f64,
+    /// Iteration limits for each channel, the channels' own limits unless the
+    /// keyframe sets them
+    pub limits: Vec<u32>,
+    pub rotation: Rotation,
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// The still config for one frame of the animation in `config`.
+pub fn frame_config(config: &Config, frame: u32) -> Config {
+    let keys = &config.keyframes;
+    let next = keys.iter()
+        .position(|key| key.frame >= frame)
+        .unwrap_or(keys.len() - 1);
+    let (a, b) = if next == 0 {
+        (&keys[0], &keys[0])
+    } else {
+        (&keys[next - 1], &keys[next])
+    };
+    let t = if b.frame > a.frame {
+        (frame.min(b.frame) - a.frame) as f64 / (b.frame - a.frame) as f64
+    } else {
+        0.0
+    };
+
+    let mut out = config.clone();
+    out.keyframes = Vec::new();
+    out.resume_from = None;
+    out.origin = Complex::from_floats(
+        lerp(a.origin.r, b.origin.r, t),
+        lerp(a.origin.i, b.origin.i, t),
+    );
+    out.zoom = lerp(a.zoom.ln(), b.zoom.ln(), t).exp();
+
+    let mut angles = [0.0; 6];
+    let (from, to) = (a.rotation.angles(), b.rotation.angles());
+    for (angle, (&x, &y)) in angles.iter_mut().zip(from.iter().zip(to.iter())) {
+        *angle = lerp(x, y, t);
+    }
+    out.rotation = Rotation::from_angles(angles);
+
+    let limits = a.limits.iter().zip(&b.limits);
+    for (channel, (&x, &y)) in out.channels.iter_mut().zip(limits) {
+        channel.limit = lerp(x as f64, y as f64, t).round() as u32;
+    }
+
+    out.max_batches = Some(config.frame_batches);
+    out.fname = config.fname.as_ref().map(|fname| frame_name(fname, frame));
+    out
+}
+
+/// Insert the frame number before the extension, `anim.png` becomes `anim-0012.png`.
+fn frame_name(fname: &str, frame: u32) -> String {
+    let path = Path::new(fname);
+    let stem = path.file_stem().map_or(String::new(), |x| x.to_string_lossy().into_owned());
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{:04}.{}", stem, frame, ext.to_string_lossy()),
+        None => format!("{}-{:04}", stem, frame),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Whether chains that converged for view `a` are a good start for view `b`.
+fn is_close(a: &Config, b: &Config) -> bool {
+    let ratio = b.zoom / a.zoom;
+    let shift = (b.origin - a.origin).norm2().sqrt() * a.zoom.min(b.zoom);
+    let turn = a.rotation
+        .angles()
+        .iter()
+        .zip(b.rotation.angles().iter())
+        .map(|(x, y)| (x - y).abs())
+        .fold(0.0, f64::max);
+    ratio > 0.5 && ratio < 2.0 && shift < 0.25 && turn < 10.0
+}
+
+/// Render every frame of the animation described by `config`'s keyframes,
+/// without a window. `on_frame` is given the frame number, the number of
+/// frames and the finished job before each frame is saved.
+pub fn run<F>(config: &Config, interrupted: &AtomicBool, mut on_frame: F) -> Result<(), RawError>
+where
+    F: FnMut(u32, u32, &RenderJob),
+{
+    let frames = config.keyframes.last().map_or(0, |key| key.frame + 1);
+    // Frames usually go in a directory of their own
+    if let Some(dir) = config.fname.as_ref().and_then(|x| Path::new(x).parent()) {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+
+    let mut previous: Option<Config> = None;
+    let mut chains = Vec::new();
+    for frame in 0..frames {
+        let frame_config = frame_config(config, frame);
+        if !previous.as_ref().map_or(false, |prev| is_close(prev, &frame_config)) {
+            chains.clear();
+        }
+
+        let mut job = RenderJob::start_with_chains(frame_config.clone(), chains)?;
+        while !job.is_finished() && !interrupted.load(Ordering::SeqCst) {
+            if !job.poll(10) {
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+        chains = job.finish();
+
+        if interrupted.load(Ordering::SeqCst) {
+            break;
+        }
+
+        on_frame(frame, frames, &job);
+        job.save()?;
+        previous = Some(frame_config);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Config, from_toml};
+    use super::{frame_config, frame_name};
+
+    fn animation() -> Config {
+        let text = r#"
+            fname = "out/anim.png"
+            frame_batches = 4
+            red_limit = 100
+            green_limit = 100
+            blue_limit = 100
+
+            [[keyframe]]
+            frame = 10
+            r = 1.0
+            i = -1.0
+            zoom = 100.0
+            limits = [200, 300, 100]
+            zr_cr = 20.0
+
+            [[keyframe]]
+            frame = 0
+            r = 0.0
+            i = 0.0
+            zoom = 1.0
+        "#;
+        from_toml("test", text).unwrap()
+    }
+
+    fn limits(config: &Config) -> Vec<u32> {
+        config.channels.iter().map(|x| x.limit).collect()
+    }
+
+    #[test]
+    fn frames_interpolate_between_keyframes() {
+        let config = frame_config(&animation(), 5);
+        assert_eq!((config.origin.r, config.origin.i), (0.5, -0.5));
+        assert!((config.zoom - 10.0).abs() < 1e-9);
+        assert_eq!(config.rotation.zr_cr, 10.0);
+        assert_eq!(limits(&config), vec![150, 200, 100]);
+        assert_eq!(config.max_batches, Some(4));
+        assert_eq!(config.fname, Some(String::from("out/anim-0005.png")));
+        assert!(config.keyframes.is_empty());
+    }
+
+    #[test]
+    fn frames_outside_the_keyframes_hold_still() {
+        let config = animation();
+        assert_eq!(frame_config(&config, 0).zoom, 1.0);
+        assert_eq!(limits(&frame_config(&config, 0)), vec![100, 100, 100]);
+        assert!((frame_config(&config, 20).zoom - 100.0).abs() < 1e-9);
+        assert_eq!(frame_config(&config, 20).origin, frame_config(&config, 10).origin);
+    }
+
+    #[test]
+    fn keyframe_limits_must_clear_min_iterations() {
+        let text = "red_min_iterations = 50\n[[keyframe]]\nlimits = [60, 10, 10]\n";
+        assert!(from_toml("test", text).is_ok());
+        let text = "red_min_iterations = 50\n[[keyframe]]\nlimits = [50, 10, 10]\n";
+        assert!(from_toml("test", text).is_err());
+    }
+
+    #[test]
+    fn frame_numbers_go_before_the_extension() {
+        assert_eq!(frame_name("anim.png", 12), "anim-0012.png");
+        assert_eq!(frame_name("out/anim.png", 3), "out/anim-0003.png");
+        assert_eq!(frame_name("./out/anim", 3), "./out/anim-0003");
+        assert_eq!(frame_name("v1.2/anim", 3), "v1.2/anim-0003");
+    }
+}
@@ -1,53 +1,73 @@
 use complex::Complex;
+use projection::Projection;
 
+/// Per-pixel counts for each channel, stored as `channels` consecutive counts
+/// per pixel.
 pub struct Buffer {
-    pub buffer: Box<[[u32; 3]]>,
+    pub buffer: Box<[u32]>,
     pub width: u32,
     pub height: u32,
+    pub channels: usize,
     pub origin: Complex,
     pub zoom: f64,
+    pub projection: Projection,
 }
 
 impl Buffer {
-    pub fn new(width: u32, height: u32, origin: Complex, zoom: f64) -> Self {
+    pub fn new(
+        width: u32,
+        height: u32,
+        channels: usize,
+        origin: Complex,
+        zoom: f64,
+        projection: Projection,
+    ) -> Self {
         Buffer {
-            buffer: vec![[0_u32; 3]; (width * height) as usize].into_boxed_slice(),
+            buffer: vec![0_u32; (width * height) as usize * channels].into_boxed_slice(),
             width: width,
             height: height,
+            channels: channels,
             origin: origin,
             zoom: zoom,
+            projection: projection,
         }
     }
 
-    pub fn project(&self, point: Complex) -> (u32, u32) {
+    /// The offset from the centre of the view of orbit point `z` with parameter `c`.
+    pub fn offset(&self, z: Complex, c: Complex) -> Complex {
+        self.projection.apply(z, c) - self.origin
+    }
+
+    pub fn project(&self, z: Complex, c: Complex) -> (u32, u32) {
         let size = if self.width > self.height {
             self.height
         } else {
             self.width
         };
         let aspect = self.width as f64 / self.height as f64;
-        let offset = point - self.origin;
+        let offset = self.offset(z, c);
         let x = ((offset.r * self.zoom + 0.5 * aspect) * size as f64) as u32;
         let y = ((offset.i * self.zoom + 0.5) * size as f64) as u32;
         (x, y)
     }
 
-    pub fn increment(&mut self, idx: usize, point: Complex) -> bool {
-        if idx >= 3 {
+    pub fn increment(&mut self, idx: usize, z: Complex, c: Complex) -> bool {
+        if idx >= self.channels {
             return false;
         }
 
-        let (x, y) = self.project(point);
+        let (x, y) = self.project(z, c);
         if x >= self.width || y >= self.height {
             return false;
         }
 
-        self.buffer[(x + y * self.width) as usize][idx] += 1;
+        let count = &mut self.buffer[(x + y * self.width) as usize * self.channels + idx];
+        *count = count.saturating_add(1);
         true
     }
 
-    pub fn check(&self, point: Complex) -> bool {
-        let (x, y) = self.project(point);
+    pub fn check(&self, z: Complex, c: Complex) -> bool {
+        let (x, y) = self.project(z, c);
         !(x >= self.width || y >= self.height)
     }
 }
@@ -0,0 +1,194 @@
+use std::io;
+
+use image;
+
+use config::Channel;
+
+/// Parameters for turning accumulated counts into 8-bit colour.
+#[derive(Clone, Copy, Debug, RustcEncodable, RustcDecodable)]
+pub struct ToneMap {
+    /// Strength of the contrast curve, 0.5 leaves the normalised counts linear
+    pub gain: f64,
+    /// Scale applied to the normalised counts before the contrast curve
+    pub exposure: f64,
+    /// Fraction of the lit pixels in each channel that should be darker than
+    /// white, 1.0 normalises by the brightest pixel
+    pub white_point: f64,
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+        ToneMap {
+            gain: 0.2,
+            exposure: 1.0,
+            white_point: 1.0,
+        }
+    }
+}
+
+impl ToneMap {
+    /// The settings that are out of range, along with what's wrong with each.
+    pub fn errors(&self) -> Vec<(&'static str, String)> {
+        let mut errors = Vec::new();
+        if !(self.gain > 0.0 && self.gain < 1.0) {
+            let message = format!("must be between 0 and 1, found {}", self.gain);
+            errors.push(("gain", message));
+        }
+        if !(self.white_point > 0.0 && self.white_point <= 1.0) {
+            let message = format!(
+                "must be greater than 0 and at most 1, found {}",
+                self.white_point
+            );
+            errors.push(("white_point", message));
+        }
+        errors
+    }
+}
+
+/// Find the count that should map to white in channel `idx`. Empty pixels are
+/// left out, so that a sparse channel isn't normalised by a count of 0.
+fn channel_white(in_buf: &[u64], channels: usize, idx: usize, white_point: f64) -> u64 {
+    let counts = in_buf.iter().skip(idx).step_by(channels).cloned();
+    if white_point >= 1.0 {
+        return counts.max().unwrap_or(0);
+    }
+
+    let mut counts: Vec<u64> = counts.filter(|&x| x > 0).collect();
+    counts.sort();
+    let pos = (white_point * counts.len().saturating_sub(1) as f64) as usize;
+    counts.get(pos).cloned().unwrap_or(0)
+}
+
+pub fn color_map_buffer(
+    width: u32,
+    height: u32,
+    window_width: u32,
+    window_height: u32,
+    channels: &[Channel],
+    in_buf: &[u64],
+    out_buf: &mut [u8],
+    tone_map: &ToneMap,
+) {
+    fn clamp(x: f64) -> u8 {
+        match x {
+            x if x <= 0.0 => 0,
+            x if x >= 255.0 => 255,
+            x => x as u8,
+        }
+    }
+
+    fn gain(x: f64, val: f64) -> f64 {
+        fn bias(x: f64, val: f64) -> f64 {
+            if val > 0.0 { x.powf(val.log(0.5)) } else { 0.0 }
+        }
+
+        let x = x.min(1.0);
+        let curve = if x < 0.5 {
+            bias(2.0 * x, 1.0 - val)
+        } else {
+            2.0 - bias(2.0 - 2.0 * x, 1.0 - val)
+        };
+        curve * 256.0
+    }
+
+    let n = channels.len();
+    let white: Vec<u64> = (0..n)
+        .map(|idx| channel_white(in_buf, n, idx, tone_map.white_point))
+        .collect();
+
+    let skip_x = (width / window_width) as usize;
+    let skip_y = (height / window_height) as usize;
+
+    // Skip rows and columns in order to down-sample appropriately
+    let pix = in_buf.chunks(width as usize * n * skip_y).flat_map(|part| {
+        part[..width as usize * n]
+            .chunks(skip_x * n)
+            .map(|x| &x[..n])
+    });
+
+    let (exposure, val) = (tone_map.exposure, tone_map.gain);
+    for (target, elem) in out_buf.chunks_mut(3).zip(pix) {
+        let mut color = [0.0; 3];
+        for ((&count, &max), channel) in elem.iter().zip(white.iter()).zip(channels) {
+            // A channel with no counts at all stays black
+            if max == 0 {
+                continue;
+            }
+            let level = gain(exposure * count as f64 / max as f64, val);
+            for (c, &weight) in color.iter_mut().zip(channel.color.iter()) {
+                *c += level * weight;
+            }
+        }
+        target[0] = clamp(color[0]);
+        target[1] = clamp(color[1]);
+        target[2] = clamp(color[2]);
+    }
+}
+
+/// Tone-map the full resolution `buffer` into an image.
+pub fn to_image(
+    width: u32,
+    height: u32,
+    channels: &[Channel],
+    buffer: &[u64],
+    tone_map: &ToneMap,
+) -> image::RgbImage {
+    let mut image_buffer = vec![0_u8; (width * height) as usize * 3];
+    color_map_buffer(
+        width,
+        height,
+        width,
+        height,
+        channels,
+        buffer,
+        &mut image_buffer,
+        tone_map,
+    );
+    image::RgbImage::from_raw(width, height, image_buffer).unwrap()
+}
+
+/// Tone-map the full resolution `buffer` and save it as an image.
+pub fn save_image(
+    fname: &str,
+    width: u32,
+    height: u32,
+    channels: &[Channel],
+    buffer: &[u64],
+    tone_map: &ToneMap,
+) -> io::Result<()> {
+    to_image(width, height, channels, buffer, tone_map).save(fname)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_points_skip_empty_pixels() {
+        // Channel 0 lights two pixels in ten, channel 1 lights every pixel
+        let mut buf = vec![0_u64; 20];
+        buf[0] = 4;
+        buf[2] = 8;
+        for (idx, count) in buf.iter_mut().skip(1).step_by(2).enumerate() {
+            *count = idx as u64 + 1;
+        }
+
+        assert_eq!(channel_white(&buf, 2, 0, 0.5), 4);
+        assert_eq!(channel_white(&buf, 2, 0, 1.0), 8);
+        assert_eq!(channel_white(&buf, 2, 1, 0.5), 5);
+        assert_eq!(channel_white(&[0, 0, 0], 1, 0, 0.5), 0);
+    }
+
+    #[test]
+    fn tone_maps_out_of_range_are_reported() {
+        assert!(ToneMap::default().errors().is_empty());
+
+        let tone_map = ToneMap {
+            gain: 1.0,
+            exposure: 1.0,
+            white_point: 0.0,
+        };
+        let keys: Vec<_> = tone_map.errors().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["gain", "white_point"]);
+    }
+}
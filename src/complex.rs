@@ -1,41 +1,68 @@
 use std::ops::{Add, Sub, Mul};
-use rand::{Rand, Rng};
-use rand::distributions::{IndependentSample, Range};
 
 /// A complex number consisting of a real and imaginary component
-#[derive(Default, PartialEq, Clone, Copy, Debug)]
+#[derive(Default, PartialEq, Clone, Copy, Debug, RustcEncodable, RustcDecodable)]
 pub struct Complex {
     pub r: f64,
     pub i: f64,
 }
 
-impl Rand for Complex {
-    fn rand<R: Rng>(rand: &mut R) -> Self {
-        let range = Range::new(-2.0, 2.0);
-        loop {
-            let c = Complex {
-                r: range.ind_sample(rand),
-                i: range.ind_sample(rand),
-            };
-            if !c.escaped() {
-                return c;
-            }
-        }
-    }
-}
-
 impl Complex {
     pub fn from_floats(r: f64, i: f64) -> Self {
         Complex { r: r, i: i }
     }
 
-    pub fn escaped(&self) -> bool {
-        self.norm2() > 4.0
-    }
-
     pub fn norm2(&self) -> f64 {
         self.r * self.r + self.i * self.i
     }
+
+    /// Whether the point is inside the main cardioid or the period-2 bulb of
+    /// the Mandelbrot set, and so can never escape.
+    pub fn in_main_bulbs(&self) -> bool {
+        let x = self.r - 0.25;
+        let y2 = self.i * self.i;
+        let q = x * x + y2;
+        if q * (q + x) <= 0.25 * y2 {
+            return true;
+        }
+
+        let x = self.r + 1.0;
+        x * x + y2 <= 0.0625
+    }
+
+    pub fn conj(&self) -> Self {
+        Complex {
+            r: self.r,
+            i: -self.i,
+        }
+    }
+
+    /// Raise to a positive integer power by repeated squaring
+    pub fn powi(self, mut n: u32) -> Self {
+        let mut base = self;
+        let mut result = Complex::from_floats(1.0, 0.0);
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            n >>= 1;
+        }
+        result
+    }
+
+    /// Raise to a real power using the principal branch
+    pub fn powf(self, d: f64) -> Self {
+        if self.r == 0.0 && self.i == 0.0 {
+            return self;
+        }
+        let r = self.norm2().sqrt().powf(d);
+        let theta = self.i.atan2(self.r) * d;
+        Complex {
+            r: r * theta.cos(),
+            i: r * theta.sin(),
+        }
+    }
 }
 
 impl Mul for Complex {
@@ -97,3 +124,21 @@ impl Sub<f64> for Complex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Complex;
+
+    #[test]
+    fn main_bulbs() {
+        let inside = [(0.0, 0.0), (0.25, 0.0), (0.2, 0.0), (-0.5, 0.3), (-1.0, 0.0), (-1.2, 0.1)];
+        for &(r, i) in &inside {
+            assert!(Complex::from_floats(r, i).in_main_bulbs(), "{} {}", r, i);
+        }
+
+        let outside = [(0.3, 0.0), (-0.75, 0.1), (-1.3, 0.0), (0.0, 1.0), (-2.0, 0.0)];
+        for &(r, i) in &outside {
+            assert!(!Complex::from_floats(r, i).in_main_bulbs(), "{} {}", r, i);
+        }
+    }
+}
@@ -1,16 +1,57 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs::File;
-use std::io::Read;
-use std::env;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
-use toml;
+use toml::{self, Table, Value};
 use num_cpus;
 
 use complex::Complex;
+use color::ToneMap;
+use fractal::{Bailout, Formula, Norm};
+use projection::Rotation;
+use animation::Keyframe;
+use presets;
 
-#[derive(Clone)]
+/// One layer of the image: orbits escaping after at least `min_iterations`
+/// but fewer than `limit` iterations are counted here, and the counts are drawn
+/// in `color`.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Channel {
+    pub min_iterations: u32,
+    pub limit: u32,
+    /// Red, green and blue weights between 0 and 1
+    pub color: [f64; 3],
+}
+
+impl Channel {
+    /// Whether an orbit with this escape time belongs in the channel. The
+    /// anti-Buddhabrot counts only the orbits that never escape.
+    pub fn accepts(&self, escape_time: Option<u32>, anti: bool) -> bool {
+        match escape_time {
+            Some(steps) => !anti && steps >= self.min_iterations && steps < self.limit,
+            None => anti,
+        }
+    }
+}
+
+#[derive(Clone, RustcEncodable, RustcDecodable)]
 pub struct Config {
     pub use_metropolis: bool,
-    pub limits: [u32; 3],
+    /// Plot the orbits that stay bounded instead of the ones that escape
+    pub anti_buddhabrot: bool,
+    pub formula: Formula,
+    pub bailout: Bailout,
+    /// Render the orbits of a Julia set with this fixed c
+    pub julia: Option<Complex>,
+    /// Rotation of the 4D orbit space before it's projected onto the image
+    pub rotation: Rotation,
+    /// Views to animate between, rendering one image per frame
+    pub keyframes: Vec<Keyframe>,
+    /// Number of batches rendered for each frame of an animation
+    pub frame_batches: u32,
+    pub channels: Vec<Channel>,
     pub width: u32,
     pub height: u32,
     pub window_width: u32,
@@ -19,55 +60,802 @@ pub struct Config {
     pub n_threads: u32,
     pub warmup_count: u32,
     pub max_batches: Option<u32>,
+    pub max_seconds: Option<u32>,
+    /// Seed for the workers' random streams. With a seed and `max_batches`,
+    /// the same number of threads always renders the same counts and writes
+    /// the same raw file.
+    pub seed: Option<u64>,
+    pub headless: bool,
+    /// Seconds between progress reports
+    pub progress_interval: f64,
+    /// File that each progress report is appended to as a line of JSON
+    pub progress_log: Option<String>,
     pub origin: Complex,
     pub zoom: f64,
     pub fname: Option<String>,
     pub save_raw: bool,
+    pub tone_map: ToneMap,
+    pub resume_from: Option<String>,
+}
+
+/// A view worth coming back to, from a locations file.
+#[derive(Clone, Debug)]
+pub struct NamedLocation {
+    pub name: String,
+    pub origin: Complex,
+    pub zoom: f64,
+    /// Suggested iteration limits for each channel
+    pub limits: Option<Vec<u32>>,
+    pub notes: Option<String>,
+}
+
+/// Where `location = "name"` is looked up when `locations_file` isn't set,
+/// next to the config file that sets the location.
+pub const DEFAULT_LOCATIONS: &'static str = "locations.toml";
+
+/// Where a config error was found. Files are numbered in the order they were
+/// read, starting with the config file itself and then the files it extends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Location {
+    /// Somewhere in a config file, but not on any one line
+    File(usize),
+    /// A line of a config file, counting from 1
+    Line(usize, usize),
+    /// The options given on the command line
+    CommandLine,
+}
+
+/// A problem found while loading a config file.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { at: Location, error: io::Error },
+    /// The file isn't valid TOML
+    Parse { at: Location, message: String },
+    /// The command line doesn't make sense
+    Argument(String),
+    UnknownKey {
+        at: Location,
+        key: String,
+        suggestion: Option<&'static str>,
+    },
+    WrongType {
+        at: Location,
+        key: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The value has the right type but isn't allowed
+    Invalid {
+        at: Location,
+        key: String,
+        message: String,
+    },
+}
+
+impl ConfigError {
+    pub fn location(&self) -> Location {
+        match *self {
+            ConfigError::Argument(_) => Location::CommandLine,
+            ConfigError::Io { at, .. } |
+            ConfigError::Parse { at, .. } |
+            ConfigError::UnknownKey { at, .. } |
+            ConfigError::WrongType { at, .. } |
+            ConfigError::Invalid { at, .. } => at,
+        }
+    }
+
+    /// Whether the rest of the config could still be read, so that loading it
+    /// leniently would use a default instead of failing.
+    pub fn is_recoverable(&self) -> bool {
+        match *self {
+            ConfigError::Io { .. } | ConfigError::Parse { .. } | ConfigError::Argument(_) => false,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io { ref error, .. } => write!(f, "{}", error),
+            ConfigError::Parse { ref message, .. } |
+            ConfigError::Argument(ref message) => write!(f, "{}", message),
+            ConfigError::UnknownKey { ref key, suggestion, .. } => {
+                write!(f, "unknown key `{}`", key)?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, ", did you mean `{}`?", suggestion)?;
+                }
+                Ok(())
+            }
+            ConfigError::WrongType { ref key, expected, found, .. } => {
+                write!(f, "`{}` should be {}, found {}", key, expected, found)
+            }
+            ConfigError::Invalid { ref key, ref message, .. } => {
+                write!(f, "`{}` {}", key, message)
+            }
+        }
+    }
+}
+
+/// Every problem found in a config, along with the `files` it was read from.
+#[derive(Debug)]
+pub struct ConfigErrors {
+    pub files: Vec<String>,
+    pub errors: Vec<ConfigError>,
 }
 
-pub fn get_config() -> Config {
-    fn get_conf(path: &str) -> Result<toml::Table, ()> {
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, err) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match err.location() {
+                Location::File(file) => write!(f, "{}: {}", self.files[file], err)?,
+                Location::Line(file, line) => {
+                    write!(f, "{}:{}: {}", self.files[file], line, err)?
+                }
+                Location::CommandLine => write!(f, "command line: {}", err)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The number of single character insertions, deletions and substitutions
+/// that turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, x) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &y) in b.iter().enumerate() {
+            let substitute = diagonal + if x == y { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The known key that `key` was most likely meant to be.
+fn suggest(key: &str, known: &[&'static str]) -> Option<&'static str> {
+    known
+        .iter()
+        .map(|&candidate| (edit_distance(key, candidate), candidate))
+        .filter(|&(distance, candidate)| {
+            distance < key.len() && distance <= 2.max(candidate.len() / 3)
+        })
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Where a key lives in the file: the top level, or the `index`th table called `name`.
+type Section<'a> = Option<(&'a str, usize)>;
+
+/// Lay `top` over `base`. Keys in `top` win, except that tables like
+/// `[rotation]` are merged key by key.
+fn merge(mut base: Table, top: Table) -> Table {
+    for (key, value) in top {
+        let value = match (base.remove(&key), value) {
+            (Some(Value::Table(mut inner)), Value::Table(top_inner)) => {
+                inner.extend(top_inner);
+                Value::Table(inner)
+            }
+            (_, value) => value,
+        };
+        base.insert(key, value);
+    }
+    base
+}
+
+/// Reads config files and typed values out of them, recording each problem
+/// along with where it came from.
+struct Checker {
+    /// The path and text of each file read
+    sources: Vec<(String, String)>,
+    /// Keys set on the command line, as `key` or `table.key`
+    overrides: HashSet<String>,
+    errors: Vec<ConfigError>,
+}
+
+impl Checker {
+    /// Read the config file at `path` along with the files it extends, which
+    /// are found relative to it. Returns `None` if any of them can't be read.
+    fn load(&mut self, path: &str, chain: &mut Vec<PathBuf>) -> Option<Table> {
+        let file = self.sources.len();
         let mut text = String::new();
-        if let Ok(ref mut f) = File::open(path) {
-            if f.read_to_string(&mut text).is_err() {
-                return Err(());
+        let read = File::open(path).and_then(|mut f| f.read_to_string(&mut text));
+        if let Err(err) = read {
+            self.sources.push((String::from(path), text));
+            self.errors.push(ConfigError::Io {
+                at: Location::File(file),
+                error: err,
+            });
+            return None;
+        }
+
+        let table = self.parse(path, text)?;
+
+        let base = match table.get("extends") {
+            None => return Some(table),
+            Some(&Value::String(ref base)) => base.clone(),
+            Some(value) => {
+                let at = self.locate_in(file, None, "extends");
+                self.errors.push(ConfigError::WrongType {
+                    at: at,
+                    key: String::from("extends"),
+                    expected: "a string",
+                    found: value.type_str(),
+                });
+                return Some(table);
             }
+        };
+
+        let base = match Path::new(path).parent() {
+            Some(dir) => dir.join(&base),
+            None => PathBuf::from(&base),
+        };
+        chain.push(Path::new(path).canonicalize().unwrap_or_else(|_| PathBuf::from(path)));
+        if base.canonicalize().map_or(false, |base| chain.contains(&base)) {
+            let at = self.locate_in(file, None, "extends");
+            self.errors.push(ConfigError::Invalid {
+                at: at,
+                key: String::from("extends"),
+                message: format!("can't extend `{}`, which already extends this file",
+                                 base.display()),
+            });
+            return Some(table);
+        }
+        self.load(&base.to_string_lossy(), chain).map(|base| merge(base, table))
+    }
+
+    /// Parse the TOML `text` read from `name`.
+    fn parse(&mut self, name: &str, text: String) -> Option<Table> {
+        let file = self.sources.len();
+        let (table, errors): (Option<Table>, Vec<ConfigError>) = {
+            let mut parser = toml::Parser::new(&text);
+            let table = parser.parse();
+            let errors = parser
+                .errors
+                .iter()
+                .map(|err| {
+                    ConfigError::Parse {
+                        at: Location::Line(file, parser.to_linecol(err.lo).0 + 1),
+                        message: err.desc.clone(),
+                    }
+                })
+                .collect();
+            (table, errors)
+        };
+        self.sources.push((String::from(name), text));
+        if table.is_none() {
+            self.errors.extend(errors);
+        }
+        table
+    }
+
+    /// Read the `[[location]]` tables of the locations file at `path`.
+    fn read_locations(&mut self, path: &str) -> Option<Vec<NamedLocation>> {
+        let file = self.sources.len();
+        let table = self.load(path, &mut Vec::new())?;
+        self.check_keys(&table, None, &["extends", "location"]);
+
+        let mut locations: Vec<NamedLocation> = Vec::new();
+        for (idx, table) in self.get_tables(&table, "location").into_iter().enumerate() {
+            let section = Some(("location", idx));
+            self.check_keys(table, section, &["name", "r", "i", "zoom", "limits", "notes"]);
+            let name = match self.get_str(table, section, "name") {
+                Some(name) => name,
+                None => {
+                    self.errors.push(ConfigError::Invalid {
+                        at: Location::File(file),
+                        key: String::from("name"),
+                        message: String::from("is needed for every location"),
+                    });
+                    continue;
+                }
+            };
+            if locations.iter().any(|x| x.name == name) {
+                let message = format!("`{}` is used for more than one location", name);
+                self.invalid(section, "name", message);
+            }
+            locations.push(NamedLocation {
+                origin: Complex::from_floats(
+                    self.get_f64(table, section, "r", 0.0),
+                    self.get_f64(table, section, "i", 0.0),
+                ),
+                zoom: self.get_positive(table, section, "zoom", 1.0),
+                limits: self.get_u32_list(table, section, "limits"),
+                notes: self.get_str(table, section, "notes"),
+                name: name,
+            });
+        }
+        Some(locations)
+    }
+
+    /// Find the line that sets `key` in `section`, searching the files in the
+    /// order they take precedence.
+    fn locate(&self, section: Section, key: &str) -> Location {
+        let dotted = match section {
+            Some((name, _)) => format!("{}.{}", name, key),
+            None => String::from(key),
+        };
+        if self.overrides.contains(&dotted) {
+            return Location::CommandLine;
+        }
+
+        for file in 0..self.sources.len() {
+            let at = self.locate_in(file, section, key);
+            if at != Location::File(file) {
+                return at;
+            }
+        }
+        Location::File(0)
+    }
+
+    /// Find the line that sets `key` in `section` of one file by scanning its
+    /// text, since the parsed tables don't remember where they came from.
+    fn locate_in(&self, file: usize, section: Section, key: &str) -> Location {
+        let mut current: Section = None;
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (n, line) in self.sources[file].1.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with("[[") {
+                let name = line[2..].split(']').next().unwrap_or("").trim();
+                let count = counts.entry(name).or_insert(0);
+                current = Some((name, *count));
+                *count += 1;
+            } else if line.starts_with('[') {
+                current = Some((line[1..].split(']').next().unwrap_or("").trim(), 0));
+            } else if current == section && line.contains('=') &&
+                       line.split('=').next().map(str::trim) == Some(key)
+            {
+                return Location::Line(file, n + 1);
+            }
+        }
+        Location::File(file)
+    }
+
+    /// The directory of the config file that sets `key`, which paths in that
+    /// file are relative to. Keys given on the command line use the directory
+    /// of the config file named there, if there is one.
+    fn dir_of(&self, key: &str) -> PathBuf {
+        let file = match self.locate(None, key) {
+            Location::File(file) | Location::Line(file, _) => file,
+            Location::CommandLine => 0,
+        };
+        self.sources
+            .get(file)
+            .and_then(|&(ref path, _)| Path::new(path).parent())
+            .map_or_else(PathBuf::new, Path::to_path_buf)
+    }
+
+    fn unknown_key(&mut self, section: Section, key: &str, known: &[&'static str]) {
+        let at = self.locate(section, key);
+        self.errors.push(ConfigError::UnknownKey {
+            at: at,
+            key: String::from(key),
+            suggestion: suggest(key, known),
+        });
+    }
+
+    fn wrong_type(&mut self, section: Section, key: &str, expected: &'static str, value: &Value) {
+        let at = self.locate(section, key);
+        self.errors.push(ConfigError::WrongType {
+            at: at,
+            key: String::from(key),
+            expected: expected,
+            found: value.type_str(),
+        });
+    }
+
+    fn invalid(&mut self, section: Section, key: &str, message: String) {
+        let at = self.locate(section, key);
+        self.errors.push(ConfigError::Invalid {
+            at: at,
+            key: String::from(key),
+            message: message,
+        });
+    }
+
+    fn check_keys(&mut self, table: &Table, section: Section, known: &[&'static str]) {
+        for key in table.keys() {
+            if !known.contains(&&key[..]) {
+                self.unknown_key(section, key, known);
+            }
+        }
+    }
+
+    fn get_opt_u64(&mut self, table: &Table, section: Section, key: &str) -> Option<u64> {
+        match table.get(key) {
+            None => None,
+            Some(&Value::Integer(x)) if x >= 0 => Some(x as u64),
+            Some(&Value::Integer(x)) => {
+                self.invalid(section, key, format!("can't be negative, found {}", x));
+                None
+            }
+            Some(value) => {
+                self.wrong_type(section, key, "an integer", value);
+                None
+            }
+        }
+    }
+
+    fn get_opt_u32(&mut self, table: &Table, section: Section, key: &str) -> Option<u32> {
+        self.get_opt_u64(table, section, key).and_then(|x| if x > u32::max_value() as u64 {
+            self.invalid(section, key, format!("is too large, found {}", x));
+            None
         } else {
-            return Err(());
+            Some(x as u32)
+        })
+    }
+
+    fn get_u32(&mut self, table: &Table, section: Section, key: &str, val: u32) -> u32 {
+        self.get_opt_u32(table, section, key).unwrap_or(val)
+    }
+
+    /// Like `get_u32`, but zero isn't allowed either.
+    fn get_count(&mut self, table: &Table, section: Section, key: &str, val: u32) -> u32 {
+        match self.get_opt_u32(table, section, key) {
+            Some(0) => {
+                self.invalid(section, key, String::from("must be at least 1"));
+                val
+            }
+            Some(x) => x,
+            None => val,
         }
-        toml::Parser::new(&text).parse().ok_or(())
     }
 
-    let default = toml::Table::new();
-    let conf = if let Some(fname) = env::args().nth(1) {
-        match get_conf(&fname) {
-            Ok(conf) => conf,
-            Err(()) => {
-                println!("Error attempting to read config '{}'", fname);
-                default
+    /// Floats can be written as integers too, like `zoom = 1`.
+    fn get_f64(&mut self, table: &Table, section: Section, key: &str, val: f64) -> f64 {
+        match table.get(key) {
+            None => val,
+            Some(&Value::Float(x)) => x,
+            Some(&Value::Integer(x)) => x as f64,
+            Some(value) => {
+                self.wrong_type(section, key, "a number", value);
+                val
             }
         }
-    } else {
-        default
+    }
+
+    /// Like `get_f64`, but only numbers above zero are allowed.
+    fn get_positive(&mut self, table: &Table, section: Section, key: &str, val: f64) -> f64 {
+        let x = self.get_f64(table, section, key, val);
+        if x > 0.0 {
+            x
+        } else {
+            self.invalid(section, key, format!("must be greater than 0, found {}", x));
+            val
+        }
+    }
+
+    fn get_bool(&mut self, table: &Table, section: Section, key: &str, val: bool) -> bool {
+        match table.get(key) {
+            None => val,
+            Some(&Value::Boolean(x)) => x,
+            Some(value) => {
+                self.wrong_type(section, key, "true or false", value);
+                val
+            }
+        }
+    }
+
+    fn get_str(&mut self, table: &Table, section: Section, key: &str) -> Option<String> {
+        match table.get(key) {
+            None => None,
+            Some(&Value::String(ref x)) => Some(x.clone()),
+            Some(value) => {
+                self.wrong_type(section, key, "a string", value);
+                None
+            }
+        }
+    }
+
+    fn get_color(
+        &mut self,
+        table: &Table,
+        section: Section,
+        key: &str,
+        val: [f64; 3],
+    ) -> [f64; 3] {
+        let value = match table.get(key) {
+            None => return val,
+            Some(value) => value,
+        };
+        let parts: Vec<f64> = value
+            .as_slice()
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|x| x.as_float().or_else(|| x.as_integer().map(|x| x as f64)))
+            .collect();
+        if parts.len() == 3 {
+            [parts[0], parts[1], parts[2]]
+        } else {
+            self.wrong_type(section, key, "a list of three numbers", value);
+            val
+        }
+    }
+
+    fn get_u32_list(&mut self, table: &Table, section: Section, key: &str) -> Option<Vec<u32>> {
+        let value = match table.get(key) {
+            None => return None,
+            Some(value) => value,
+        };
+        let items = value.as_slice().unwrap_or(&[]);
+        let list: Vec<u32> = items
+            .iter()
+            .filter_map(Value::as_integer)
+            .filter(|&x| x >= 0 && x <= u32::max_value() as i64)
+            .map(|x| x as u32)
+            .collect();
+        if value.as_slice().is_none() || list.len() != items.len() {
+            self.wrong_type(section, key, "a list of iteration counts", value);
+            return None;
+        }
+        Some(list)
+    }
+
+    fn get_table<'t>(&mut self, table: &'t Table, key: &str) -> Option<&'t Table> {
+        match table.get(key) {
+            None => None,
+            Some(&Value::Table(ref x)) => Some(x),
+            Some(value) => {
+                self.wrong_type(None, key, "a table", value);
+                None
+            }
+        }
+    }
+
+    /// The tables of an array of tables like `[[channel]]`.
+    fn get_tables<'t>(&mut self, table: &'t Table, key: &str) -> Vec<&'t Table> {
+        match table.get(key) {
+            None => Vec::new(),
+            Some(value) => {
+                let tables: Vec<&Table> = value
+                    .as_slice()
+                    .unwrap_or(&[])
+                    .iter()
+                    .filter_map(Value::as_table)
+                    .collect();
+                if value.as_slice().map_or(true, |x| x.len() != tables.len()) {
+                    self.wrong_type(None, key, "an array of tables", value);
+                }
+                tables
+            }
+        }
+    }
+}
+
+/// The boolean keys, which can be turned on with just `--key`.
+const FLAGS: [&'static str; 4] = ["use_metropolis", "anti_buddhabrot", "headless", "save_raw"];
+
+/// Split `args` into the config path, the `--key value` overrides, and whether
+/// to load leniently.
+fn parse_args(
+    args: &[String],
+) -> Result<(Option<String>, Vec<(String, Value)>, bool), ConfigError> {
+    let mut path = None;
+    let mut overrides = Vec::new();
+    let mut lenient = false;
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        if arg == "--lenient" {
+            lenient = true;
+        } else if arg.starts_with("--") {
+            let mut parts = arg[2..].splitn(2, '=');
+            let key = parts.next().unwrap().replace('-', "_");
+            let key = match &key[..] {
+                "output" => String::from("fname"),
+                "extends" => {
+                    return Err(ConfigError::Argument(String::from(
+                        "`--extends` can't be given on the command line, \
+                         name the file to extend as the config instead",
+                    )))
+                }
+                _ => key,
+            };
+            // Switches like `--headless` can be given without a value, so
+            // they only take a following `true` or `false`
+            let is_switch = FLAGS.contains(&&key[..]);
+            let takes_next = args.peek().map_or(false, |next| if is_switch {
+                *next == "true" || *next == "false"
+            } else {
+                !next.starts_with("--")
+            });
+            let value = match parts.next() {
+                Some(value) => parse_value(value),
+                None if takes_next => parse_value(args.next().unwrap()),
+                None => Value::Boolean(true),
+            };
+            overrides.push((key, value));
+        } else if path.is_none() {
+            path = Some(arg.clone());
+        } else {
+            return Err(ConfigError::Argument(format!("unexpected argument `{}`", arg)));
+        }
+    }
+    Ok((path, overrides, lenient))
+}
+
+/// Read a command line value as TOML, so `4096` is an integer and `true` is a
+/// boolean, falling back to a string for values like file names.
+fn parse_value(text: &str) -> Value {
+    toml::Parser::new(&format!("value = {}", text))
+        .parse()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(String::from(text)))
+}
+
+/// Read the named views in the locations file at `path`.
+pub fn read_locations(path: &str) -> Result<Vec<NamedLocation>, ConfigErrors> {
+    let mut checker = Checker {
+        sources: Vec::new(),
+        overrides: HashSet::new(),
+        errors: Vec::new(),
     };
+    let locations = checker.read_locations(path);
+    match locations {
+        Some(locations) if checker.errors.is_empty() => Ok(locations),
+        _ => {
+            checker.errors.sort_by_key(ConfigError::location);
+            Err(ConfigErrors {
+                files: checker.sources.into_iter().map(|(path, _)| path).collect(),
+                errors: checker.errors,
+            })
+        }
+    }
+}
 
-    fn get_u32(table: &toml::Table, key: &str, val: u32) -> u32 {
-        table
-            .get(key)
-            .and_then(Value::as_integer)
-            .unwrap_or(val as i64) as u32
+/// Load the config named by the first of `args` that isn't an option, with
+/// every `--key value` option setting or replacing that key. Dashes in keys
+/// are read as underscores, and keys in tables are written `--table.key`.
+///
+/// A config can build on another file with `extends = "base.toml"`, and on
+/// one of the built-in presets with `preset = "preview"`. The preset comes
+/// first, then the files from the base up, and then the options.
+///
+/// Every problem with the config is reported, and any of them fails the load.
+/// With `--lenient`, problems that still leave a usable config are printed as
+/// warnings instead, and the defaults are used in place of the bad values.
+pub fn get_config(args: &[String]) -> Result<Config, ConfigErrors> {
+    let (path, overrides, lenient) = parse_args(args).map_err(|err| {
+        ConfigErrors {
+            files: Vec::new(),
+            errors: vec![err],
+        }
+    })?;
+
+    let mut checker = Checker {
+        sources: Vec::new(),
+        overrides: HashSet::new(),
+        errors: Vec::new(),
+    };
+    for &(ref key, _) in &overrides {
+        checker.overrides.insert(String::from(key.split('.').next().unwrap()));
+        checker.overrides.insert(key.clone());
+    }
+
+    let conf = match path {
+        Some(path) => checker.load(&path, &mut Vec::new()),
+        None => Some(Table::new()),
+    };
+    let mut conf = match conf {
+        Some(conf) => conf,
+        None => {
+            return Err(ConfigErrors {
+                files: checker.sources.into_iter().map(|(path, _)| path).collect(),
+                errors: checker.errors,
+            })
+        }
+    };
+
+    for &(ref key, ref value) in &overrides {
+        let mut parts = key.splitn(2, '.');
+        let name = parts.next().unwrap();
+        match parts.next() {
+            Some(inner) => {
+                let entry = conf.entry(String::from(name))
+                    .or_insert_with(|| Value::Table(Table::new()));
+                if entry.as_table().is_none() {
+                    *entry = Value::Table(Table::new());
+                }
+                if let Value::Table(ref mut table) = *entry {
+                    table.insert(String::from(inner), value.clone());
+                }
+            }
+            None => {
+                conf.insert(String::from(name), value.clone());
+            }
+        }
+    }
+
+    if let Some(name) = checker.get_str(&conf, None, "preset") {
+        match presets::preset(&name) {
+            Some(text) => conf = merge(toml::Parser::new(text).parse().unwrap(), conf),
+            None => {
+                let message = format!(
+                    "should be one of {}, found `{}`",
+                    presets::NAMES.join(", "),
+                    name
+                );
+                checker.invalid(None, "preset", message);
+            }
+        }
+    }
+
+    let config = build_config(&mut checker, &conf);
+    checker.errors.sort_by_key(ConfigError::location);
+    if checker.errors.is_empty() {
+        return Ok(config);
     }
 
-    fn get_f64(table: &toml::Table, key: &str, val: f64) -> f64 {
-        table.get(key).and_then(Value::as_float).unwrap_or(val)
+    let errors = ConfigErrors {
+        files: checker.sources.into_iter().map(|(path, _)| path).collect(),
+        errors: checker.errors,
+    };
+    if !lenient {
+        return Err(errors);
+    }
+    // Warnings go to stderr, so that `--print-config` stays valid TOML
+    for line in errors.to_string().lines() {
+        eprintln!("Warning: {}", line);
+    }
+    Ok(config)
+}
+
+/// Read a config written by `to_toml`, such as the one stored in a raw file,
+/// where `name` says where it came from. Keys that aren't known are skipped,
+/// so that configs written by other versions can still be read.
+pub fn from_toml(name: &str, text: &str) -> Result<Config, ConfigErrors> {
+    let mut checker = Checker {
+        sources: Vec::new(),
+        overrides: HashSet::new(),
+        errors: Vec::new(),
+    };
+    let config = checker
+        .parse(name, String::from(text))
+        .map(|conf| build_config(&mut checker, &conf));
+    checker.errors.retain(|err| match *err {
+        ConfigError::UnknownKey { .. } => false,
+        _ => true,
+    });
+    match config {
+        Some(config) if checker.errors.is_empty() => Ok(config),
+        _ => {
+            checker.errors.sort_by_key(ConfigError::location);
+            Err(ConfigErrors {
+                files: checker.sources.into_iter().map(|(path, _)| path).collect(),
+                errors: checker.errors,
+            })
+        }
     }
+}
 
-    use toml::Value;
+fn build_config(checker: &mut Checker, conf: &Table) -> Config {
     let keys = [
+        "extends",
+        "preset",
         "use_metropolis",
+        "anti_buddhabrot",
+        "formula",
+        "power",
+        "escape_radius",
+        "escape_norm",
+        "julia_r",
+        "julia_i",
+        "rotation",
+        "keyframe",
+        "frame_batches",
         "red_limit",
         "green_limit",
         "blue_limit",
+        "red_min_iterations",
+        "green_min_iterations",
+        "blue_min_iterations",
+        "channel",
         "width",
         "height",
         "window_width",
@@ -76,26 +864,286 @@ pub fn get_config() -> Config {
         "n_threads",
         "warmup_count",
         "max_batches",
+        "max_seconds",
+        "seed",
+        "headless",
+        "progress_interval",
+        "progress_log",
         "r",
         "i",
         "zoom",
         "fname",
         "save_raw",
+        "gain",
+        "exposure",
+        "white_point",
+        "resume_from",
+        "location",
+        "locations_file",
     ];
+    checker.check_keys(conf, None, &keys);
+
+    let channel_tables = checker.get_tables(conf, "channel");
+
+    // Without any [[channel]] tables, fall back to the classic red/green/blue limits
+    let mut channels = if channel_tables.is_empty() {
+        vec![
+            Channel {
+                min_iterations: checker.get_u32(conf, None, "red_min_iterations", 0),
+                limit: checker.get_count(conf, None, "red_limit", 50000),
+                color: [1.0, 0.0, 0.0],
+            },
+            Channel {
+                min_iterations: checker.get_u32(conf, None, "green_min_iterations", 0),
+                limit: checker.get_count(conf, None, "green_limit", 5000),
+                color: [0.0, 1.0, 0.0],
+            },
+            Channel {
+                min_iterations: checker.get_u32(conf, None, "blue_min_iterations", 0),
+                limit: checker.get_count(conf, None, "blue_limit", 500),
+                color: [0.0, 0.0, 1.0],
+            },
+        ]
+    } else {
+        channel_tables
+            .iter()
+            .enumerate()
+            .map(|(idx, table)| {
+                let section = Some(("channel", idx));
+                checker.check_keys(table, section, &["min_iterations", "limit", "color"]);
+                Channel {
+                    min_iterations: checker.get_u32(table, section, "min_iterations", 0),
+                    limit: checker.get_count(table, section, "limit", 5000),
+                    color: checker.get_color(table, section, "color", [1.0, 1.0, 1.0]),
+                }
+            })
+            .collect()
+    };
+
+    let width = checker.get_count(conf, None, "width", 512);
+    let height = checker.get_count(conf, None, "height", 512);
+    let mut window_width = checker.get_count(conf, None, "window_width", 512);
+    let mut window_height = checker.get_count(conf, None, "window_height", 512);
+
+    let formula_name = checker.get_str(conf, None, "formula");
+    let formula = match formula_name.as_ref().map_or("mandelbrot", |x| &x[..]) {
+        "mandelbrot" => Formula::Mandelbrot,
+        "multibrot" => {
+            // Below 2 the sets spill out of the disk of radius 2 that gets sampled
+            let power = checker.get_f64(conf, None, "power", 2.0);
+            if power == 2.0 {
+                Formula::Mandelbrot
+            } else if power > 2.0 && power.fract() == 0.0 {
+                Formula::Multibrot(power as u32)
+            } else if power > 2.0 {
+                Formula::MultibrotReal(power)
+            } else {
+                let message = format!("must be at least 2, found {}", power);
+                checker.invalid(None, "power", message);
+                Formula::Mandelbrot
+            }
+        }
+        "burning_ship" => Formula::BurningShip,
+        "tricorn" | "mandelbar" => Formula::Tricorn,
+        "celtic" => Formula::Celtic,
+        other => {
+            let message = format!(
+                "should be one of mandelbrot, multibrot, burning_ship, tricorn or celtic, \
+                 found `{}`",
+                other
+            );
+            checker.invalid(None, "formula", message);
+            Formula::Mandelbrot
+        }
+    };
+
+    let multibrot = formula_name.as_ref().map_or(false, |x| x == "multibrot");
+    if conf.contains_key("power") && !multibrot {
+        let message = String::from("only applies with `formula = \"multibrot\"`");
+        checker.invalid(None, "power", message);
+    }
+
+    let norm_name = checker.get_str(conf, None, "escape_norm");
+    let norm = match norm_name.as_ref().map_or("euclidean", |x| &x[..]) {
+        "euclidean" => Norm::Euclidean,
+        "max" => Norm::Max,
+        "manhattan" => Norm::Manhattan,
+        other => {
+            let message = format!(
+                "should be one of euclidean, max or manhattan, found `{}`",
+                other
+            );
+            checker.invalid(None, "escape_norm", message);
+            Norm::Euclidean
+        }
+    };
+    let bailout = Bailout {
+        radius: checker.get_positive(conf, None, "escape_radius", Bailout::default().radius),
+        norm: norm,
+    };
+
+    let julia = if conf.contains_key("julia_r") || conf.contains_key("julia_i") {
+        Some(Complex::from_floats(
+            checker.get_f64(conf, None, "julia_r", 0.0),
+            checker.get_f64(conf, None, "julia_i", 0.0),
+        ))
+    } else {
+        None
+    };
+
+    let mut angles = [0.0; 6];
+    if let Some(rotation_table) = checker.get_table(conf, "rotation") {
+        let section = Some(("rotation", 0));
+        checker.check_keys(rotation_table, section, &Rotation::PLANES);
+        for (angle, plane) in angles.iter_mut().zip(Rotation::PLANES.iter()) {
+            *angle = checker.get_f64(rotation_table, section, plane, 0.0);
+        }
+    }
+    let rotation = Rotation::from_angles(angles);
+    let mut origin = Complex::from_floats(
+        checker.get_f64(conf, None, "r", -0.4),
+        checker.get_f64(conf, None, "i", 0.0),
+    );
+    let mut zoom = checker.get_positive(conf, None, "zoom", 0.35);
 
-    for key in conf.keys() {
-        if !keys.contains(&&key[..]) {
-            println!("Unrecognized key `{}` in config.", key);
+    // A named location replaces the view from the config files, but not any
+    // part of it that was given on the command line. Like `extends`, the
+    // locations file is found relative to the config file that names it.
+    let locations_file = checker.get_str(conf, None, "locations_file");
+    if let Some(name) = checker.get_str(conf, None, "location") {
+        let path = match locations_file {
+            Some(ref path) if checker.overrides.contains("locations_file") => PathBuf::from(path),
+            Some(ref path) => checker.dir_of("locations_file").join(path),
+            None => checker.dir_of("location").join(DEFAULT_LOCATIONS),
+        };
+        let path = path.to_string_lossy().into_owned();
+        let locations = checker.read_locations(&path).unwrap_or_default();
+        match locations.iter().find(|x| x.name == name) {
+            Some(location) => {
+                let set = |key: &str| checker.overrides.contains(key);
+                if !set("r") {
+                    origin.r = location.origin.r;
+                }
+                if !set("i") {
+                    origin.i = location.origin.i;
+                }
+                if !set("zoom") {
+                    zoom = location.zoom;
+                }
+                let limits_set = ["red_limit", "green_limit", "blue_limit", "channel"]
+                    .iter()
+                    .any(|key| set(key));
+                match location.limits {
+                    Some(ref limits) if !limits_set => {
+                        for (channel, &limit) in channels.iter_mut().zip(limits) {
+                            channel.limit = limit;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            None if checker.errors.iter().all(ConfigError::is_recoverable) => {
+                let mut message = format!(
+                    "should name one of the locations in `{}`, found `{}`",
+                    path,
+                    name
+                );
+                let closest = locations
+                    .iter()
+                    .min_by_key(|x| edit_distance(&name, &x.name))
+                    .filter(|x| edit_distance(&name, &x.name) <= 2.max(x.name.len() / 3));
+                if let Some(closest) = closest {
+                    message = format!("{}, did you mean `{}`?", message, closest.name);
+                }
+                checker.errors.push(ConfigError::Invalid {
+                    at: checker.locate(None, "location"),
+                    key: String::from("location"),
+                    message: message,
+                });
+            }
+            None => (),
+        }
+    }
+
+    // A channel that no escape time fits in would never find a sample
+    for (idx, channel) in channels.iter_mut().enumerate() {
+        if channel.min_iterations < channel.limit {
+            continue;
+        }
+        let message = format!(
+            "must be less than the channel's limit of {}, found {}",
+            channel.limit,
+            channel.min_iterations
+        );
+        if channel_tables.is_empty() {
+            let key = ["red", "green", "blue"][idx].to_string() + "_min_iterations";
+            checker.invalid(None, &key, message);
+        } else {
+            checker.invalid(Some(("channel", idx)), "min_iterations", message);
         }
+        channel.min_iterations = 0;
     }
 
-    let width = get_u32(&conf, "width", 512);
-    let height = get_u32(&conf, "height", 512);
-    let mut window_width = get_u32(&conf, "window_width", 512);
-    let mut window_height = get_u32(&conf, "window_height", 512);
+    let mut keyframe_keys = vec!["frame", "r", "i", "zoom", "limits"];
+    keyframe_keys.extend_from_slice(&Rotation::PLANES);
+    let mut keyframes: Vec<Keyframe> = checker
+        .get_tables(conf, "keyframe")
+        .iter()
+        .enumerate()
+        .map(|(idx, table)| {
+            let section = Some(("keyframe", idx));
+            checker.check_keys(table, section, &keyframe_keys);
+
+            let mut angles = rotation.angles();
+            for (angle, plane) in angles.iter_mut().zip(Rotation::PLANES.iter()) {
+                *angle = checker.get_f64(table, section, plane, *angle);
+            }
+            let own_limits: Vec<u32> = channels.iter().map(|x| x.limit).collect();
+            let mut limits = checker
+                .get_u32_list(table, section, "limits")
+                .unwrap_or_else(|| own_limits.clone());
+            // The frames in between interpolate the limits, so they stay above
+            // `min_iterations` as long as the keyframes' own limits do
+            let too_low = channels
+                .iter()
+                .zip(&limits)
+                .find(|&(channel, &limit)| limit <= channel.min_iterations);
+            let message = if limits.len() != channels.len() {
+                Some(format!(
+                    "should have one limit for each of the {} channels",
+                    channels.len()
+                ))
+            } else if let Some((channel, &limit)) = too_low {
+                Some(format!(
+                    "must be greater than each channel's min_iterations, found {} for a \
+                     channel with min_iterations = {}",
+                    limit,
+                    channel.min_iterations
+                ))
+            } else {
+                None
+            };
+            if let Some(message) = message {
+                checker.invalid(section, "limits", message);
+                limits = own_limits;
+            }
+
+            Keyframe {
+                frame: checker.get_u32(table, section, "frame", 0),
+                origin: Complex::from_floats(
+                    checker.get_f64(table, section, "r", origin.r),
+                    checker.get_f64(table, section, "i", origin.i),
+                ),
+                zoom: checker.get_positive(table, section, "zoom", zoom),
+                limits: limits,
+                rotation: Rotation::from_angles(angles),
+            }
+        })
+        .collect();
+    keyframes.sort_by_key(|key| key.frame);
 
     if window_width > width {
-        println!(
+        eprintln!(
             "Warning: decreased window width to fit image width. Requested {}, using {}.",
             window_width,
             width
@@ -104,7 +1152,7 @@ pub fn get_config() -> Config {
     }
 
     if window_height > height {
-        println!(
+        eprintln!(
             "Warning: decreased window height to fit image height. Requested {}, using {}.",
             window_height,
             height
@@ -112,30 +1160,218 @@ pub fn get_config() -> Config {
         window_height = height;
     }
 
+    let default_tone_map = ToneMap::default();
+    let tone_map = ToneMap {
+        gain: checker.get_f64(conf, None, "gain", default_tone_map.gain),
+        exposure: checker.get_f64(conf, None, "exposure", default_tone_map.exposure),
+        white_point: checker.get_f64(conf, None, "white_point", default_tone_map.white_point),
+    };
+    for (key, message) in tone_map.errors() {
+        checker.invalid(None, key, message);
+    }
+
     Config {
-        use_metropolis: conf.get("use_metropolis")
-            .and_then(Value::as_bool)
-            .unwrap_or(true),
-        limits: [
-            get_u32(&conf, "red_limit", 50000),
-            get_u32(&conf, "green_limit", 5000),
-            get_u32(&conf, "blue_limit", 500),
-        ],
+        use_metropolis: checker.get_bool(conf, None, "use_metropolis", true),
+        anti_buddhabrot: checker.get_bool(conf, None, "anti_buddhabrot", false),
+        formula: formula,
+        bailout: bailout,
+        julia: julia,
+        rotation: rotation,
+        keyframes: keyframes,
+        frame_batches: checker.get_count(conf, None, "frame_batches", 64),
+        channels: channels,
         width: width,
         height: height,
         window_width: window_width,
         window_height: window_height,
-        batch_steps: get_u32(&conf, "batch_steps", 5000),
-        n_threads: get_u32(&conf, "n_threads", num_cpus::get() as u32),
-        warmup_count: get_u32(&conf, "warmup_count", 10),
-        max_batches: conf.get("max_batches")
-            .and_then(Value::as_integer)
-            .map(|x| x as u32),
-        origin: Complex::from_floats(get_f64(&conf, "r", -0.4), get_f64(&conf, "i", 0.0)),
-        zoom: get_f64(&conf, "zoom", 0.35),
-        fname: conf.get("fname").and_then(Value::as_str).map(String::from),
-        save_raw: conf.get("save_raw")
-            .and_then(Value::as_bool)
-            .unwrap_or(false),
+        batch_steps: checker.get_count(conf, None, "batch_steps", 5000),
+        n_threads: checker.get_count(conf, None, "n_threads", num_cpus::get() as u32),
+        warmup_count: checker.get_u32(conf, None, "warmup_count", 10),
+        max_batches: checker.get_opt_u32(conf, None, "max_batches"),
+        max_seconds: checker.get_opt_u32(conf, None, "max_seconds"),
+        seed: checker.get_opt_u64(conf, None, "seed"),
+        headless: checker.get_bool(conf, None, "headless", false),
+        progress_interval: checker.get_positive(conf, None, "progress_interval", 1.0),
+        progress_log: checker.get_str(conf, None, "progress_log"),
+        origin: origin,
+        zoom: zoom,
+        fname: checker.get_str(conf, None, "fname"),
+        save_raw: checker.get_bool(conf, None, "save_raw", false),
+        tone_map: tone_map,
+        resume_from: checker.get_str(conf, None, "resume_from"),
+    }
+}
+
+/// Write `config` as TOML in the form it's read in, so the output can be
+/// loaded again.
+pub fn to_toml(config: &Config) -> String {
+    fn int(x: u32) -> Value {
+        Value::Integer(x as i64)
+    }
+    fn string(x: &str) -> Value {
+        Value::String(String::from(x))
+    }
+    fn planes(table: &mut Table, rotation: &Rotation) {
+        for (plane, &angle) in Rotation::PLANES.iter().zip(rotation.angles().iter()) {
+            table.insert(String::from(*plane), Value::Float(angle));
+        }
+    }
+
+    let mut conf = Table::new();
+    {
+        let mut set = |key: &str, value: Value| {
+            conf.insert(String::from(key), value);
+        };
+
+        set("use_metropolis", Value::Boolean(config.use_metropolis));
+        set("anti_buddhabrot", Value::Boolean(config.anti_buddhabrot));
+        let (formula, power) = match config.formula {
+            Formula::Mandelbrot => ("mandelbrot", None),
+            Formula::Multibrot(d) => ("multibrot", Some(int(d))),
+            Formula::MultibrotReal(d) => ("multibrot", Some(Value::Float(d))),
+            Formula::BurningShip => ("burning_ship", None),
+            Formula::Tricorn => ("tricorn", None),
+            Formula::Celtic => ("celtic", None),
+        };
+        set("formula", string(formula));
+        if let Some(power) = power {
+            set("power", power);
+        }
+        set("escape_radius", Value::Float(config.bailout.radius));
+        set(
+            "escape_norm",
+            string(match config.bailout.norm {
+                Norm::Euclidean => "euclidean",
+                Norm::Max => "max",
+                Norm::Manhattan => "manhattan",
+            }),
+        );
+        if let Some(julia) = config.julia {
+            set("julia_r", Value::Float(julia.r));
+            set("julia_i", Value::Float(julia.i));
+        }
+        if config.rotation != Rotation::default() {
+            let mut rotation = Table::new();
+            planes(&mut rotation, &config.rotation);
+            set("rotation", Value::Table(rotation));
+        }
+
+        let channels = config.channels
+            .iter()
+            .map(|channel| {
+                let mut table = Table::new();
+                table.insert(String::from("min_iterations"), int(channel.min_iterations));
+                table.insert(String::from("limit"), int(channel.limit));
+                let color = channel.color.iter().map(|&x| Value::Float(x)).collect();
+                table.insert(String::from("color"), Value::Array(color));
+                Value::Table(table)
+            })
+            .collect();
+        set("channel", Value::Array(channels));
+
+        if !config.keyframes.is_empty() {
+            let keyframes = config.keyframes
+                .iter()
+                .map(|key| {
+                    let mut table = Table::new();
+                    table.insert(String::from("frame"), int(key.frame));
+                    table.insert(String::from("r"), Value::Float(key.origin.r));
+                    table.insert(String::from("i"), Value::Float(key.origin.i));
+                    table.insert(String::from("zoom"), Value::Float(key.zoom));
+                    let limits = key.limits.iter().map(|&x| int(x)).collect();
+                    table.insert(String::from("limits"), Value::Array(limits));
+                    planes(&mut table, &key.rotation);
+                    Value::Table(table)
+                })
+                .collect();
+            set("keyframe", Value::Array(keyframes));
+        }
+        set("frame_batches", int(config.frame_batches));
+
+        set("width", int(config.width));
+        set("height", int(config.height));
+        set("window_width", int(config.window_width));
+        set("window_height", int(config.window_height));
+        set("batch_steps", int(config.batch_steps));
+        set("n_threads", int(config.n_threads));
+        set("warmup_count", int(config.warmup_count));
+        if let Some(max_batches) = config.max_batches {
+            set("max_batches", int(max_batches));
+        }
+        if let Some(max_seconds) = config.max_seconds {
+            set("max_seconds", int(max_seconds));
+        }
+        if let Some(seed) = config.seed {
+            set("seed", Value::Integer(seed as i64));
+        }
+        set("headless", Value::Boolean(config.headless));
+        set("progress_interval", Value::Float(config.progress_interval));
+        if let Some(ref progress_log) = config.progress_log {
+            set("progress_log", string(progress_log));
+        }
+        set("r", Value::Float(config.origin.r));
+        set("i", Value::Float(config.origin.i));
+        set("zoom", Value::Float(config.zoom));
+        if let Some(ref fname) = config.fname {
+            set("fname", string(fname));
+        }
+        set("save_raw", Value::Boolean(config.save_raw));
+        set("gain", Value::Float(config.tone_map.gain));
+        set("exposure", Value::Float(config.tone_map.exposure));
+        set("white_point", Value::Float(config.tone_map.white_point));
+        if let Some(ref resume_from) = config.resume_from {
+            set("resume_from", string(resume_from));
+        }
+    }
+    Value::Table(conf).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("zoom", "zoom"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("window_widht", "window_width"), 2);
+    }
+
+    #[test]
+    fn suggestions() {
+        let known = ["blue_limit", "fname", "warmup_count", "width", "zoom"];
+        assert_eq!(suggest("blue_llmit", &known), Some("blue_limit"));
+        assert_eq!(suggest("frame", &known), Some("fname"));
+        assert_eq!(suggest("warup_count", &known), Some("warmup_count"));
+        assert_eq!(suggest("xyz", &known), None);
+        assert_eq!(suggest("z", &known), None);
+    }
+
+    #[test]
+    fn written_configs_read_back_the_same() {
+        let config = get_config(&[String::from("examples/julia.toml")]).unwrap();
+        let text = to_toml(&config);
+        assert_eq!(to_toml(&from_toml("test", &text).unwrap()), text);
+    }
+
+    #[test]
+    fn reading_written_configs_skips_unknown_keys() {
+        let config = from_toml("test", "zoom = 2.0\nnot_a_key = 1\n").unwrap();
+        assert_eq!(config.zoom, 2.0);
+        assert!(from_toml("test", "zoom = -2.0\n").is_err());
+    }
+
+    #[test]
+    fn powers_need_a_multibrot() {
+        let config = from_toml("test", "formula = \"multibrot\"\npower = 3\n").unwrap();
+        assert_eq!(config.formula, Formula::Multibrot(3));
+        let config = from_toml("test", "formula = \"multibrot\"\npower = 2.5\n").unwrap();
+        assert_eq!(config.formula, Formula::MultibrotReal(2.5));
+
+        assert!(from_toml("test", "power = 3\n").is_err());
+        assert!(from_toml("test", "formula = \"celtic\"\npower = 3\n").is_err());
+        assert!(from_toml("test", "formula = \"multibrot\"\npower = 1.5\n").is_err());
     }
 }
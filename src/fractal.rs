@@ -0,0 +1,364 @@
+use rand::Rng;
+
+use complex::Complex;
+use config::{Channel, Config};
+
+/// The iteration that generates each orbit.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Formula {
+    /// z² + c
+    Mandelbrot,
+    /// z^d + c for an integer d
+    Multibrot(u32),
+    /// z^d + c for a real d
+    MultibrotReal(f64),
+    /// (|Re z| + i|Im z|)² + c
+    BurningShip,
+    /// conj(z)² + c, also known as the Mandelbar
+    Tricorn,
+    /// |Re z²| + i Im z² + c
+    Celtic,
+}
+
+impl Formula {
+    #[inline]
+    pub fn step(&self, z: Complex, c: Complex) -> Complex {
+        match *self {
+            Formula::Mandelbrot => z * z + c,
+            Formula::Multibrot(d) => z.powi(d) + c,
+            Formula::MultibrotReal(d) => z.powf(d) + c,
+            Formula::BurningShip => {
+                let z = Complex::from_floats(z.r.abs(), z.i.abs());
+                z * z + c
+            }
+            Formula::Tricorn => {
+                let z = z.conj();
+                z * z + c
+            }
+            Formula::Celtic => {
+                let z2 = z * z;
+                Complex::from_floats(z2.r.abs(), z2.i) + c
+            }
+        }
+    }
+}
+
+/// How the size of a point is measured when checking whether it escaped.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Norm {
+    Euclidean,
+    /// The larger of |Re z| and |Im z|
+    Max,
+    /// |Re z| + |Im z|
+    Manhattan,
+}
+
+/// The test for when an orbit has escaped.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Bailout {
+    pub radius: f64,
+    pub norm: Norm,
+}
+
+impl Default for Bailout {
+    fn default() -> Self {
+        Bailout {
+            radius: 2.0,
+            norm: Norm::Euclidean,
+        }
+    }
+}
+
+impl Bailout {
+    #[inline]
+    pub fn escaped(&self, z: Complex) -> bool {
+        match self.norm {
+            Norm::Euclidean => z.norm2() > self.radius * self.radius,
+            Norm::Max => z.r.abs().max(z.i.abs()) > self.radius,
+            Norm::Manhattan => z.r.abs() + z.i.abs() > self.radius,
+        }
+    }
+}
+
+/// Orbits that come back this close (squared) to an earlier point are taken
+/// to be periodic. This is down at the rounding error of points near the set,
+/// since escaping orbits that crawl past a parabolic point, such as those just
+/// outside the cusp, can move by less than 1e-10 a step.
+const PERIOD_TOLERANCE: f64 = 1e-30;
+
+/// Everything that decides how an orbit is computed and whether it's plotted.
+///
+/// The samplers explore a space of points without knowing what they mean. For
+/// the Buddhabrot a sample is c, and every orbit starts at z₀ = c. With `julia`
+/// set, c is fixed and a sample is the starting point z₀ instead.
+#[derive(Clone, Copy, Debug)]
+pub struct Fractal {
+    pub formula: Formula,
+    pub bailout: Bailout,
+    pub julia: Option<Complex>,
+    /// Plot the orbits that stay bounded instead of the ones that escape
+    pub anti: bool,
+    /// Stop iterating orbits that have fallen into a cycle. The anti-Buddhabrot
+    /// plots those orbits in full, so it can't cut them short.
+    pub detect_cycles: bool,
+}
+
+impl Fractal {
+    pub fn from_config(config: &Config) -> Self {
+        Fractal {
+            formula: config.formula,
+            bailout: config.bailout,
+            julia: config.julia,
+            anti: config.anti_buddhabrot,
+            detect_cycles: !config.anti_buddhabrot,
+        }
+    }
+
+    /// The parameter c of the orbit that starts from `sample`.
+    #[inline]
+    pub fn parameter(&self, sample: Complex) -> Complex {
+        self.julia.unwrap_or(sample)
+    }
+
+    /// Whether the orbit of `sample` is known to never escape without iterating
+    /// it. This only rejects points for the Mandelbrot set's parameter plane,
+    /// and never for the anti-Buddhabrot, which needs exactly those orbits.
+    #[inline]
+    pub fn is_interior(&self, sample: Complex) -> bool {
+        !self.anti && self.julia.is_none() && self.formula == Formula::Mandelbrot &&
+            sample.in_main_bulbs()
+    }
+
+    /// The radius of a disk about 0 that holds every sample whose orbit stays
+    /// bounded. The formulas all grow like |z|² or faster, so this is 2 for the
+    /// parameter plane and max(2, |c|) for a Julia set, whatever the bailout.
+    pub fn sample_radius(&self) -> f64 {
+        match self.julia {
+            Some(c) => c.norm2().sqrt().max(2.0),
+            None => 2.0,
+        }
+    }
+
+    /// A uniformly random point inside the disk of `sample_radius`.
+    pub fn random_in_domain<R: Rng>(&self, rng: &mut R) -> Complex {
+        let radius = self.sample_radius();
+        loop {
+            let c = Complex::from_floats(
+                (2.0 * rng.gen::<f64>() - 1.0) * radius,
+                (2.0 * rng.gen::<f64>() - 1.0) * radius,
+            );
+            if c.norm2() <= radius * radius {
+                return c;
+            }
+        }
+    }
+
+    /// A uniformly random point inside the disk of `sample_radius` that isn't
+    /// known to be interior.
+    pub fn random_point<R: Rng>(&self, rng: &mut R) -> Complex {
+        loop {
+            let c = self.random_in_domain(rng);
+            if !self.is_interior(c) {
+                return c;
+            }
+        }
+    }
+
+    /// Compute the orbit of `sample`, returning the escape time if it escapes
+    /// within `iterations` steps. Orbits found to be periodic are cut short,
+    /// since they will never escape.
+    pub fn evaluate(
+        &self,
+        sample: Complex,
+        iterations: u32,
+        orbit: &mut Vec<Complex>,
+    ) -> Option<u32> {
+        orbit.clear();
+        let c = self.parameter(sample);
+        let mut z = sample;
+
+        // Brent's cycle detection: compare against a saved point, which is
+        // moved forward after 1, 2, 4, 8... steps
+        let mut saved = z;
+        let mut period = 1;
+        let mut steps = 0;
+        for i in 0..iterations {
+            orbit.push(z);
+            z = self.formula.step(z, c);
+            if self.bailout.escaped(z) {
+                return Some(i);
+            }
+
+            if self.detect_cycles {
+                if (z - saved).norm2() < PERIOD_TOLERANCE {
+                    return None;
+                }
+                steps += 1;
+                if steps == period {
+                    steps = 0;
+                    period *= 2;
+                    saved = z;
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether an orbit with this escape time should be counted in `channel`.
+    pub fn accepts(&self, channel: &Channel, escape_time: Option<u32>) -> bool {
+        channel.accepts(escape_time, self.anti)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use complex::Complex;
+    use super::*;
+
+    fn is_close(a: Complex, b: Complex) -> bool {
+        (a - b).norm2() < 1e-20
+    }
+
+    fn mandelbrot() -> Fractal {
+        Fractal {
+            formula: Formula::Mandelbrot,
+            bailout: Bailout::default(),
+            julia: None,
+            anti: false,
+            detect_cycles: true,
+        }
+    }
+
+    #[test]
+    fn formulas_step_known_points() {
+        let c = Complex::from_floats(0.5, -0.25);
+        let cases = [
+            (Formula::Mandelbrot, (1.0, 1.0), (0.0, 2.0)),
+            (Formula::Multibrot(3), (1.0, 1.0), (-2.0, 2.0)),
+            (Formula::Multibrot(4), (1.0, 1.0), (-4.0, 0.0)),
+            (Formula::MultibrotReal(2.5), (4.0, 0.0), (32.0, 0.0)),
+            (Formula::MultibrotReal(3.0), (1.0, 1.0), (-2.0, 2.0)),
+            (Formula::BurningShip, (-1.0, -2.0), (-3.0, 4.0)),
+            (Formula::Tricorn, (1.0, 2.0), (-3.0, -4.0)),
+            (Formula::Celtic, (1.0, 2.0), (3.0, 4.0)),
+        ];
+        for &(formula, (r, i), (fr, fi)) in &cases {
+            let z = Complex::from_floats(r, i);
+            let expected = Complex::from_floats(fr, fi) + c;
+            assert!(is_close(formula.step(z, c), expected), "{:?} of {:?}", formula, z);
+        }
+    }
+
+    #[test]
+    fn bailouts_measure_with_their_norm() {
+        let bailout = |norm| Bailout {
+            radius: 2.0,
+            norm: norm,
+        };
+        // Inside all three, outside only the Manhattan norm, outside all but the max norm
+        let points = [(1.0, 0.5), (1.5, 1.0), (1.9, 1.9)];
+        let cases = [
+            (Norm::Euclidean, [false, false, true]),
+            (Norm::Max, [false, false, false]),
+            (Norm::Manhattan, [false, true, true]),
+        ];
+        for &(norm, expected) in &cases {
+            for (&(r, i), &escaped) in points.iter().zip(expected.iter()) {
+                let z = Complex::from_floats(r, i);
+                assert_eq!(bailout(norm).escaped(z), escaped, "{:?} at {:?}", norm, z);
+                assert!(bailout(norm).escaped(z * 3.0), "{:?} at {:?}", norm, z * 3.0);
+            }
+            // The radius itself is still inside
+            assert!(!bailout(norm).escaped(Complex::from_floats(0.0, -2.0)));
+        }
+    }
+
+    #[test]
+    fn julia_orbits_start_at_the_sample_with_a_fixed_c() {
+        let c = Complex::from_floats(-0.8, 0.156);
+        let sample = Complex::from_floats(0.1, 0.2);
+        let mut fractal = mandelbrot();
+        assert_eq!(fractal.parameter(sample), sample);
+        assert!(fractal.is_interior(sample));
+
+        fractal.julia = Some(c);
+        assert_eq!(fractal.parameter(sample), c);
+        // The main cardioid test is about c, so it says nothing about z₀
+        assert!(!fractal.is_interior(sample));
+
+        let mut orbit = Vec::new();
+        fractal.evaluate(sample, 3, &mut orbit);
+        let second = sample * sample + c;
+        let expected = [sample, second, second * second + c];
+        assert_eq!(orbit.len(), expected.len());
+        for (&z, &expected) in orbit.iter().zip(expected.iter()) {
+            assert!(is_close(z, expected), "{:?} != {:?}", z, expected);
+        }
+    }
+
+    #[test]
+    fn samples_come_from_a_disk_that_ignores_the_bailout() {
+        let mut fractal = mandelbrot();
+        fractal.bailout.radius = 1000.0;
+        assert_eq!(fractal.sample_radius(), 2.0);
+        fractal.julia = Some(Complex::from_floats(-0.8, 0.156));
+        assert_eq!(fractal.sample_radius(), 2.0);
+        fractal.julia = Some(Complex::from_floats(3.0, -4.0));
+        assert_eq!(fractal.sample_radius(), 5.0);
+
+        fractal.julia = None;
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for _ in 0..1000 {
+            let point = fractal.random_point(&mut rng);
+            assert!(point.norm2() <= 4.0);
+            assert!(!fractal.is_interior(point));
+        }
+    }
+
+    #[test]
+    fn cycle_detection_keeps_escape_times() {
+        let mut fractal = mandelbrot();
+        let escape_times = |fractal: &mut Fractal, c: Complex, limit: u32| {
+            let mut orbit = Vec::new();
+            fractal.detect_cycles = false;
+            let plain = fractal.evaluate(c, limit, &mut orbit);
+            fractal.detect_cycles = true;
+            (plain, fractal.evaluate(c, limit, &mut orbit))
+        };
+
+        for y in 0..60 {
+            for x in 0..75 {
+                let c = Complex::from_floats(-2.0 + x as f64 / 30.0, -1.0 + y as f64 / 30.0);
+                let (plain, detected) = escape_times(&mut fractal, c, 2000);
+                assert_eq!(plain, detected, "c = {:?}", c);
+            }
+        }
+
+        // Just outside the cusp of the cardioid and the neck at -0.75, orbits
+        // crawl past a parabolic point for a long time before escaping
+        for k in 4..13 {
+            let eps = 10_f64.powi(-k);
+            let c = Complex::from_floats(0.25 + eps, 0.0);
+            let (plain, detected) = escape_times(&mut fractal, c, 5_000_000);
+            assert!(plain.is_some(), "{}", eps);
+            assert_eq!(plain, detected, "cusp + {}", eps);
+        }
+        for k in 3..7 {
+            let eps = 10_f64.powi(-k);
+            let c = Complex::from_floats(-0.75, eps);
+            let (plain, detected) = escape_times(&mut fractal, c, 5_000_000);
+            assert!(plain.is_some(), "{}", eps);
+            assert_eq!(plain, detected, "neck + {}i", eps);
+        }
+
+        // Orbits that settle into a cycle are still cut short
+        let mut orbit = Vec::new();
+        for &(r, i) in &[(-0.1, 0.1), (-1.3, 0.0), (-0.12, 0.75)] {
+            let c = Complex::from_floats(r, i);
+            assert_eq!(fractal.evaluate(c, 1_000_000, &mut orbit), None);
+            assert!(orbit.len() < 10_000, "c = {:?}", c);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! Render the Buddhabrot and its relatives by plotting the orbits of points
+//! under iteration.
+//!
+//! A `RenderJob` built from a `Config` runs the sampler on worker threads and
+//! accumulates their counts, which can then be tone-mapped into an image:
+//!
+//! ```no_run
+//! use buddhabrot::{Config, RenderJob};
+//!
+//! let args = [String::from("examples/default.toml")];
+//! let config: Config = buddhabrot::get_config(&args).unwrap();
+//! let mut job = RenderJob::start(config).unwrap();
+//! job.run_batches(16);
+//! job.to_image().save("default.png").unwrap();
+//! ```
+
+extern crate image;
+extern crate rand;
+extern crate flate2;
+extern crate bincode;
+extern crate rustc_serialize;
+extern crate toml;
+extern crate num_cpus;
+
+pub mod complex;
+pub mod buffer;
+pub mod config;
+pub mod presets;
+pub mod color;
+pub mod fractal;
+pub mod projection;
+pub mod sampler;
+pub mod stats;
+pub mod progress;
+pub mod raw;
+pub mod render;
+pub mod animation;
+
+pub use config::{Config, get_config};
+pub use render::RenderJob;
@@ -0,0 +1,56 @@
+//! Built-in starting points for configs, chosen with `preset = "name"`. Every
+//! setting in a preset can still be changed by the config or the command line.
+
+/// The names of the presets, in the order they're listed.
+pub const NAMES: [&'static str; 3] = ["preview", "print-quality", "deep-zoom"];
+
+/// A quick, rough render for finding a view: small, with short orbits and
+/// little warm-up.
+const PREVIEW: &'static str = r#"
+width = 512
+height = 512
+red_limit = 5000
+green_limit = 500
+blue_limit = 50
+batch_steps = 1000
+warmup_count = 4
+max_batches = 256
+"#;
+
+/// A large render with enough batches for the faint orbits to smooth out,
+/// saving the raw counts so it can be re-toned or resumed.
+const PRINT_QUALITY: &'static str = r#"
+width = 4096
+height = 4096
+red_limit = 50000
+green_limit = 5000
+blue_limit = 500
+batch_steps = 10000
+warmup_count = 20
+max_batches = 8192
+save_raw = true
+"#;
+
+/// Small features deep in the set need long orbits, and more warm-up for the
+/// chains to find the few orbits that land in view.
+const DEEP_ZOOM: &'static str = r#"
+width = 2048
+height = 2048
+red_limit = 500000
+green_limit = 50000
+blue_limit = 5000
+batch_steps = 2000
+warmup_count = 50
+use_metropolis = true
+save_raw = true
+"#;
+
+/// The TOML for the preset called `name`.
+pub fn preset(name: &str) -> Option<&'static str> {
+    match name {
+        "preview" => Some(PREVIEW),
+        "print-quality" => Some(PRINT_QUALITY),
+        "deep-zoom" => Some(DEEP_ZOOM),
+        _ => None,
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use rustc_serialize::json;
+
+use render::RenderJob;
+
+/// How one channel's Metropolis chains are doing.
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct ChannelProgress {
+    /// Fraction of proposals that were accepted
+    pub acceptance: f64,
+    /// Fraction of proposals rejected for escaping outside the channel's band
+    pub out_of_band: f64,
+    /// Fraction of proposals rejected for missing the view
+    pub out_of_view: f64,
+    /// Speed-up from cutting periodic orbits short
+    pub speedup: f64,
+    /// Chains that never found a starting sample in view, and so plot nothing
+    pub unseeded: u64,
+}
+
+/// A snapshot of how a render is going.
+#[derive(Clone, Debug, RustcEncodable)]
+pub struct Progress {
+    pub batches: u32,
+    /// Seconds spent rendering, including any runs this one resumed from
+    pub elapsed: f64,
+    /// Samples proposed per second during this run
+    pub samples_per_second: f64,
+    /// Orbits evaluated during this run
+    pub orbits: u64,
+    /// Estimated seconds until `max_batches` is reached
+    pub eta: Option<f64>,
+    pub channels: Vec<ChannelProgress>,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn join<F: Fn(&ChannelProgress) -> String>(channels: &[ChannelProgress], f: F) -> String {
+            channels.iter().map(f).collect::<Vec<_>>().join("/")
+        }
+
+        write!(
+            f,
+            "{} Batches in {:.0} seconds, {:.0} samples/s, {} orbits",
+            self.batches,
+            self.elapsed,
+            self.samples_per_second,
+            self.orbits
+        )?;
+        if let Some(eta) = self.eta {
+            write!(f, ", ETA {:.0} seconds", eta)?;
+        }
+        write!(
+            f,
+            "; accepted {}, out of band {}, out of view {}, periodicity speed-up {}",
+            join(&self.channels, |x| format!("{:.0}%", x.acceptance * 100.0)),
+            join(&self.channels, |x| format!("{:.0}%", x.out_of_band * 100.0)),
+            join(&self.channels, |x| format!("{:.0}%", x.out_of_view * 100.0)),
+            join(&self.channels, |x| format!("{:.1}x", x.speedup))
+        )?;
+        if self.channels.iter().any(|x| x.unseeded > 0) {
+            write!(
+                f,
+                "; no starting sample found for {} chains",
+                join(&self.channels, |x| x.unseeded.to_string())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Decides when to report the progress of a render, every `progress_interval`
+/// seconds, and appends each report to the `progress_log` file if there is one.
+/// Showing the reports is up to the caller.
+pub struct Reporter {
+    interval: Duration,
+    last_report: Instant,
+    log: Option<File>,
+}
+
+impl Reporter {
+    /// Fails if the progress log can't be opened.
+    pub fn new(job: &RenderJob) -> io::Result<Self> {
+        let secs = job.config.progress_interval.max(0.0);
+        let log = match job.config.progress_log {
+            Some(ref path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+        Ok(Reporter {
+            interval: Duration::new(secs as u64, (secs.fract() * 1e9) as u32),
+            last_report: Instant::now(),
+            log: log,
+        })
+    }
+
+    /// Report the progress of `job` if the interval has passed since the last report.
+    pub fn tick(&mut self, job: &RenderJob) -> io::Result<Option<Progress>> {
+        if self.last_report.elapsed() >= self.interval {
+            self.report(job).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Log the progress of `job` and return it. If writing the log fails, the
+    /// error is returned instead and nothing more is logged.
+    pub fn report(&mut self, job: &RenderJob) -> io::Result<Progress> {
+        self.last_report = Instant::now();
+        let progress = job.progress();
+        if let Some(mut log) = self.log.take() {
+            let line = json::encode(&progress).unwrap();
+            writeln!(log, "{}", line)?;
+            self.log = Some(log);
+        }
+        Ok(progress)
+    }
+}
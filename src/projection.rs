@@ -0,0 +1,140 @@
+use complex::Complex;
+
+/// A rotation of the 4D orbit space (Re z, Im z, Re c, Im c), given as angles
+/// in degrees for the rotation in each plane. The planes are applied in the
+/// order they are listed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Rotation {
+    pub zr_zi: f64,
+    pub zr_cr: f64,
+    pub zr_ci: f64,
+    pub zi_cr: f64,
+    pub zi_ci: f64,
+    pub cr_ci: f64,
+}
+
+impl Rotation {
+    /// The names used for each plane in the config, in the order they're applied.
+    pub const PLANES: [&'static str; 6] = ["zr_zi", "zr_cr", "zr_ci", "zi_cr", "zi_ci", "cr_ci"];
+
+    pub fn angles(&self) -> [f64; 6] {
+        [
+            self.zr_zi,
+            self.zr_cr,
+            self.zr_ci,
+            self.zi_cr,
+            self.zi_ci,
+            self.cr_ci,
+        ]
+    }
+
+    pub fn from_angles(angles: [f64; 6]) -> Self {
+        Rotation {
+            zr_zi: angles[0],
+            zr_cr: angles[1],
+            zr_ci: angles[2],
+            zi_cr: angles[3],
+            zi_ci: angles[4],
+            cr_ci: angles[5],
+        }
+    }
+}
+
+/// Maps a point of an orbit onto the image plane, keeping the first two axes
+/// of the rotated 4D space.
+#[derive(Clone, Copy, Debug)]
+pub struct Projection {
+    rows: [[f64; 4]; 2],
+    identity: bool,
+}
+
+impl Projection {
+    pub fn new(rotation: &Rotation) -> Self {
+        const AXES: [(usize, usize); 6] = [(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)];
+
+        let mut matrix = [[0.0; 4]; 4];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+
+        for (&(a, b), &angle) in AXES.iter().zip(rotation.angles().iter()) {
+            if angle == 0.0 {
+                continue;
+            }
+            let (sin, cos) = angle.to_radians().sin_cos();
+            // Multiply on the left by the rotation in plane (a, b)
+            for col in 0..4 {
+                let (x, y) = (matrix[a][col], matrix[b][col]);
+                matrix[a][col] = cos * x - sin * y;
+                matrix[b][col] = sin * x + cos * y;
+            }
+        }
+
+        Projection {
+            rows: [matrix[0], matrix[1]],
+            identity: *rotation == Rotation::default(),
+        }
+    }
+
+    /// Project the orbit point `z` of an orbit with parameter `c` onto the image plane.
+    #[inline]
+    pub fn apply(&self, z: Complex, c: Complex) -> Complex {
+        if self.identity {
+            return z;
+        }
+        let v = [z.r, z.i, c.r, c.i];
+        let dot = |row: &[f64; 4]| row.iter().zip(v.iter()).map(|(a, b)| a * b).sum();
+        Complex::from_floats(dot(&self.rows[0]), dot(&self.rows[1]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use complex::Complex;
+    use super::{Projection, Rotation};
+
+    fn assert_close(a: Complex, b: Complex) {
+        assert!((a - b).norm2() < 1e-20, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn no_rotation_keeps_z() {
+        let (z, c) = (Complex::from_floats(0.3, -0.7), Complex::from_floats(1.5, 2.5));
+        assert_eq!(Projection::new(&Rotation::default()).apply(z, c), z);
+    }
+
+    #[test]
+    fn quarter_turns_bring_c_into_view() {
+        let (z, c) = (Complex::from_floats(0.3, -0.7), Complex::from_floats(1.5, 2.5));
+        let rotation = Rotation {
+            zr_cr: 90.0,
+            ..Rotation::default()
+        };
+        assert_close(Projection::new(&rotation).apply(z, c), Complex::from_floats(-c.r, z.i));
+
+        let rotation = Rotation {
+            zr_cr: 90.0,
+            zi_ci: 90.0,
+            ..Rotation::default()
+        };
+        assert_close(Projection::new(&rotation).apply(z, c), Complex::from_floats(-c.r, -c.i));
+    }
+
+    #[test]
+    fn full_turns_change_nothing() {
+        let (z, c) = (Complex::from_floats(0.3, -0.7), Complex::from_floats(1.5, 2.5));
+        let rotation = Rotation::from_angles([360.0, -360.0, 720.0, 360.0, 0.0, 360.0]);
+        assert_close(Projection::new(&rotation).apply(z, c), z);
+    }
+
+    #[test]
+    fn turning_the_z_plane_rotates_the_image() {
+        let (z, c) = (Complex::from_floats(1.0, 0.0), Complex::from_floats(1.5, 2.5));
+        let rotation = Rotation {
+            zr_zi: 30.0,
+            ..Rotation::default()
+        };
+        let (sin, cos) = 30.0_f64.to_radians().sin_cos();
+        assert_close(Projection::new(&rotation).apply(z, c), Complex::from_floats(cos, sin));
+    }
+}
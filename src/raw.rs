@@ -0,0 +1,379 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+
+use bincode;
+use bincode::rustc_serialize::{encode_into, decode_from, DecodingError, EncodingError};
+use flate2;
+
+use config::{Config, ConfigErrors, from_toml, to_toml};
+
+/// Written at the start of every raw file so that other files can be rejected.
+pub const RAW_MAGIC: u32 = 0x4242_5257;
+/// Bump this whenever the layout of raw files changes after a release. The
+/// config is stored as TOML, so adding config keys doesn't need a new version.
+pub const RAW_VERSION: u32 = 1;
+
+/// Everything needed to interpret a raw file without the TOML that produced it.
+pub struct RawHeader {
+    pub config: Config,
+    pub batches: u32,
+    /// Total render time in seconds, left at 0 by seeded renders so that their
+    /// files are reproducible
+    pub elapsed: f64,
+}
+
+/// A raw accumulation file read back from disk.
+pub struct RawBuf {
+    pub header: RawHeader,
+    pub content: Vec<u64>,
+}
+
+#[derive(Debug)]
+pub enum RawError {
+    Io(io::Error),
+    Encode(EncodingError),
+    Decode(DecodingError),
+    NotRaw,
+    Version(u32),
+    /// The config stored in the header can't be read
+    Config(ConfigErrors),
+    Mismatch(String),
+}
+
+impl fmt::Display for RawError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RawError::Io(ref err) => write!(f, "{}", err),
+            RawError::Encode(ref err) => write!(f, "failed to encode raw data: {}", err),
+            RawError::Decode(ref err) => write!(f, "failed to decode raw data: {}", err),
+            RawError::NotRaw => {
+                write!(
+                    f,
+                    "not a raw file, or a raw file from before the format was versioned, \
+                     which can only be resumed with the config that rendered it"
+                )
+            }
+            RawError::Version(found) => {
+                write!(
+                    f,
+                    "raw file has format version {}, but only version {} is supported",
+                    found,
+                    RAW_VERSION
+                )
+            }
+            RawError::Config(ref err) => write!(f, "raw file has a bad config: {}", err),
+            RawError::Mismatch(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for RawError {
+    fn from(err: io::Error) -> Self {
+        RawError::Io(err)
+    }
+}
+
+impl From<EncodingError> for RawError {
+    fn from(err: EncodingError) -> Self {
+        RawError::Encode(err)
+    }
+}
+
+impl From<DecodingError> for RawError {
+    fn from(err: DecodingError) -> Self {
+        RawError::Decode(err)
+    }
+}
+
+impl RawHeader {
+    /// Make sure that counts with this header can be added to a render of `config`.
+    pub fn check_matches(&self, config: &Config) -> Result<(), RawError> {
+        let own = &self.config;
+        if own.width != config.width || own.height != config.height {
+            return Err(RawError::Mismatch(format!(
+                "raw file is {}x{} but the config is {}x{}",
+                own.width,
+                own.height,
+                config.width,
+                config.height
+            )));
+        }
+
+        if own.origin != config.origin || own.zoom != config.zoom ||
+            own.rotation != config.rotation
+        {
+            return Err(RawError::Mismatch(format!(
+                "raw file shows ({}, {}) at zoom {} rotated by {:?} but the config shows \
+                 ({}, {}) at zoom {} rotated by {:?}",
+                own.origin.r,
+                own.origin.i,
+                own.zoom,
+                own.rotation,
+                config.origin.r,
+                config.origin.i,
+                config.zoom,
+                config.rotation
+            )));
+        }
+
+        if own.formula != config.formula {
+            return Err(RawError::Mismatch(format!(
+                "raw file uses the {:?} formula but the config uses {:?}",
+                own.formula,
+                config.formula
+            )));
+        }
+
+        if own.bailout != config.bailout {
+            return Err(RawError::Mismatch(format!(
+                "raw file uses the bailout {:?} but the config uses {:?}",
+                own.bailout,
+                config.bailout
+            )));
+        }
+
+        if own.julia != config.julia {
+            return Err(RawError::Mismatch(format!(
+                "raw file uses the Julia parameter {:?} but the config uses {:?}",
+                own.julia,
+                config.julia
+            )));
+        }
+
+        if own.anti_buddhabrot != config.anti_buddhabrot {
+            return Err(RawError::Mismatch(String::from(
+                "only one of the raw file and the config is an anti-Buddhabrot",
+            )));
+        }
+
+        let own_limits: Vec<_> = own.channels
+            .iter()
+            .map(|x| (x.min_iterations, x.limit))
+            .collect();
+        let limits: Vec<_> = config.channels
+            .iter()
+            .map(|x| (x.min_iterations, x.limit))
+            .collect();
+        if own_limits != limits {
+            return Err(RawError::Mismatch(format!(
+                "raw file uses iteration bands {:?} but the config uses {:?}",
+                own_limits,
+                limits
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn write_raw(path: &str, header: &RawHeader, content: &[u64]) -> Result<(), RawError> {
+    let file = File::create(path)?;
+    let mut e = flate2::write::GzEncoder::new(file, flate2::Compression::Default);
+    encode_into(&RAW_MAGIC, &mut e, bincode::SizeLimit::Infinite)?;
+    encode_into(&RAW_VERSION, &mut e, bincode::SizeLimit::Infinite)?;
+    encode_into(&to_toml(&header.config), &mut e, bincode::SizeLimit::Infinite)?;
+    encode_into(&header.batches, &mut e, bincode::SizeLimit::Infinite)?;
+    encode_into(&header.elapsed, &mut e, bincode::SizeLimit::Infinite)?;
+    encode_into(content, &mut e, bincode::SizeLimit::Infinite)?;
+    e.finish()?;
+    Ok(())
+}
+
+pub fn read_raw(path: &str) -> Result<RawBuf, RawError> {
+    let file = File::open(path)?;
+    let mut d = flate2::read::GzDecoder::new(file)?;
+
+    let magic: u32 = decode_from(&mut d, bincode::SizeLimit::Infinite)?;
+    if magic != RAW_MAGIC {
+        return Err(RawError::NotRaw);
+    }
+    let version: u32 = decode_from(&mut d, bincode::SizeLimit::Infinite)?;
+    if version != RAW_VERSION {
+        return Err(RawError::Version(version));
+    }
+
+    let config: String = decode_from(&mut d, bincode::SizeLimit::Infinite)?;
+    let header = RawHeader {
+        config: from_toml(path, &config).map_err(RawError::Config)?,
+        batches: decode_from(&mut d, bincode::SizeLimit::Infinite)?,
+        elapsed: decode_from(&mut d, bincode::SizeLimit::Infinite)?,
+    };
+    let content: Vec<u64> = decode_from(&mut d, bincode::SizeLimit::Infinite)?;
+
+    let (width, height) = (header.config.width, header.config.height);
+    let channels = header.config.channels.len();
+    if content.len() != (width * height) as usize * channels {
+        return Err(RawError::Mismatch(format!(
+            "raw file has {} counts but its header says {}x{} with {} channels",
+            content.len(),
+            width,
+            height,
+            channels
+        )));
+    }
+
+    Ok(RawBuf {
+        header: header,
+        content: content,
+    })
+}
+
+/// Read a raw file from before the format was versioned. These only store
+/// their size and three counts per pixel, so the rest of the header comes from
+/// `config`, which should be the config that rendered them.
+pub fn read_legacy_raw(path: &str, config: &Config) -> Result<RawBuf, RawError> {
+    let file = File::open(path)?;
+    let mut d = flate2::read::GzDecoder::new(file)?;
+
+    let width: u32 = decode_from(&mut d, bincode::SizeLimit::Infinite)?;
+    let height: u32 = decode_from(&mut d, bincode::SizeLimit::Infinite)?;
+    if width != config.width || height != config.height {
+        return Err(RawError::Mismatch(format!(
+            "raw file is {}x{} but the config is {}x{}",
+            width,
+            height,
+            config.width,
+            config.height
+        )));
+    }
+    if config.channels.len() != 3 {
+        return Err(RawError::Mismatch(format!(
+            "unversioned raw files have 3 channels but the config has {}",
+            config.channels.len()
+        )));
+    }
+
+    let pixels: Vec<[u32; 3]> = decode_from(&mut d, bincode::SizeLimit::Infinite)?;
+    if pixels.len() != (width * height) as usize {
+        return Err(RawError::Mismatch(format!(
+            "raw file has {} pixels but says it is {}x{}",
+            pixels.len(),
+            width,
+            height
+        )));
+    }
+
+    Ok(RawBuf {
+        header: RawHeader {
+            config: config.clone(),
+            batches: 0,
+            elapsed: 0.0,
+        },
+        content: pixels
+            .iter()
+            .flat_map(|pixel| pixel.iter().map(|&count| count as u64))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+
+    use bincode;
+    use bincode::rustc_serialize::encode_into;
+    use flate2;
+
+    use config::{from_toml, to_toml};
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = env::temp_dir().join(format!("buddhabrot-test-{}", name));
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Write a raw file that stops after the magic number and version.
+    fn write_start(path: &str, magic: u32, version: u32) {
+        let file = File::create(path).unwrap();
+        let mut e = flate2::write::GzEncoder::new(file, flate2::Compression::Default);
+        encode_into(&magic, &mut e, bincode::SizeLimit::Infinite).unwrap();
+        encode_into(&version, &mut e, bincode::SizeLimit::Infinite).unwrap();
+        e.finish().unwrap();
+    }
+
+    #[test]
+    fn raw_files_read_back_the_same() {
+        let text = "width = 4\nheight = 2\nwindow_width = 4\nwindow_height = 2\n\
+                    r = -0.1234567891\nzoom = 3.5\nseed = 5\n";
+        let config = from_toml("test", text).unwrap();
+        let content: Vec<u64> = (0..4 * 2 * 3).collect();
+        let header = RawHeader {
+            config: config.clone(),
+            batches: 12,
+            elapsed: 3.25,
+        };
+
+        let path = temp_path("round-trip.raw");
+        write_raw(&path, &header, &content).unwrap();
+        let raw = read_raw(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(raw.content, content);
+        assert_eq!(raw.header.batches, 12);
+        assert_eq!(raw.header.elapsed, 3.25);
+        assert!(raw.header.check_matches(&config).is_ok());
+        assert_eq!(to_toml(&raw.header.config), to_toml(&config));
+    }
+
+    #[test]
+    fn unversioned_raw_files_take_their_view_from_the_config() {
+        let text = "width = 2\nheight = 1\nwindow_width = 2\nwindow_height = 1\n";
+        let config = from_toml("test", text).unwrap();
+        let pixels = vec![[1_u32, 2, 3], [u32::max_value(), 0, 7]];
+
+        let path = temp_path("unversioned.raw");
+        {
+            let file = File::create(&path).unwrap();
+            let mut e = flate2::write::GzEncoder::new(file, flate2::Compression::Default);
+            encode_into(&2_u32, &mut e, bincode::SizeLimit::Infinite).unwrap();
+            encode_into(&1_u32, &mut e, bincode::SizeLimit::Infinite).unwrap();
+            encode_into(&pixels, &mut e, bincode::SizeLimit::Infinite).unwrap();
+            e.finish().unwrap();
+        }
+        let not_raw = read_raw(&path);
+        let raw = read_legacy_raw(&path, &config);
+        let mut wider = config.clone();
+        wider.width = 3;
+        let mismatched = read_legacy_raw(&path, &wider);
+        fs::remove_file(&path).unwrap();
+
+        match not_raw {
+            Err(RawError::NotRaw) => (),
+            _ => panic!("expected a raw file error"),
+        }
+        let raw = raw.unwrap();
+        assert_eq!(raw.content, vec![1, 2, 3, u32::max_value() as u64, 0, 7]);
+        assert_eq!(raw.header.batches, 0);
+        assert!(raw.header.check_matches(&config).is_ok());
+        match mismatched {
+            Err(RawError::Mismatch(_)) => (),
+            _ => panic!("expected a mismatch error"),
+        }
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let path = temp_path("old-version.raw");
+        write_start(&path, RAW_MAGIC, RAW_VERSION - 1);
+        let result = read_raw(&path);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(RawError::Version(version)) => assert_eq!(version, RAW_VERSION - 1),
+            _ => panic!("expected a version error"),
+        }
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let path = temp_path("not-raw.raw");
+        write_start(&path, 0, RAW_VERSION);
+        let result = read_raw(&path);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(RawError::NotRaw) => (),
+            _ => panic!("expected a raw file error"),
+        }
+    }
+}
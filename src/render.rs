@@ -0,0 +1,380 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, channel};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use image::RgbImage;
+
+use color::{save_image, to_image};
+use config::Config;
+use progress::{ChannelProgress, Progress, Reporter};
+use raw::{RawError, RawHeader, read_legacy_raw, read_raw, write_raw};
+use rand;
+use sampler::{Chains, seeded_rng, worker};
+use stats::{Batch, Stats};
+
+/// Split a budget of `batches` between `n_threads` workers as evenly as possible.
+fn batch_shares(batches: u32, n_threads: u32) -> Vec<u32> {
+    (0..n_threads)
+        .map(|k| batches / n_threads + (k < batches % n_threads) as u32)
+        .collect()
+}
+
+/// The accumulation core shared by every frontend. Worker threads send their
+/// batches here, and the frontend decides how often to merge and display them.
+pub struct RenderJob {
+    pub config: Config,
+    /// The global accumulator uses wide counts so long renders can't overflow.
+    /// Like `Buffer`, each pixel holds one count per channel.
+    pub buffer: Vec<u64>,
+    pub number_batches: u32,
+    /// Statistics gathered by the workers during this run
+    pub stats: Stats,
+    /// Time spent in earlier runs that this one resumed from
+    resumed_time: Duration,
+    /// Batches rendered by earlier runs that this one resumed from
+    resumed_batches: u32,
+    start_time: Instant,
+    rx: Receiver<Batch>,
+    stop: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<Chains>>,
+}
+
+impl RenderJob {
+    /// Spawn the workers for `config`, continuing from its `resume_from` file if
+    /// one is given.
+    pub fn start(config: Config) -> Result<Self, RawError> {
+        RenderJob::start_with_chains(config, Vec::new())
+    }
+
+    /// Like `start`, but continue the Metropolis chains that `finish` returned
+    /// for a nearby view instead of warming up new ones. Each worker takes one
+    /// entry of `chains`, and any workers left over start from scratch.
+    pub fn start_with_chains(config: Config, chains: Vec<Chains>) -> Result<Self, RawError> {
+        let (buffer, number_batches, resumed_time) = match config.resume_from {
+            Some(ref path) => {
+                let raw = match read_raw(path) {
+                    Err(RawError::NotRaw) => read_legacy_raw(path, &config)?,
+                    raw => raw?,
+                };
+                raw.header.check_matches(&config)?;
+                let secs = raw.header.elapsed;
+                let resumed_time = Duration::new(secs as u64, (secs.fract() * 1e9) as u32);
+                (raw.content, raw.header.batches, resumed_time)
+            }
+            None => {
+                let size = (config.width * config.height) as usize * config.channels.len();
+                let buffer = vec![0_u64; size];
+                (buffer, 0, Duration::from_secs(0))
+            }
+        };
+
+        let (tx, rx) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // A seeded render with a batch budget splits the budget between the
+        // workers up front. Each then renders the same batches whichever
+        // thread happens to be fastest, so the result is reproducible.
+        let n_threads = config.n_threads;
+        let shares = match (config.seed, config.max_batches) {
+            (Some(_), Some(max)) => {
+                let remaining = max.saturating_sub(number_batches);
+                batch_shares(remaining, n_threads).into_iter().map(Some).collect()
+            }
+            _ => vec![None; n_threads as usize],
+        };
+
+        let mut chains = chains.into_iter();
+        let mut workers = Vec::with_capacity(n_threads as usize);
+        for (index, share) in shares.into_iter().enumerate() {
+            let tx = tx.clone();
+            let config = config.clone();
+            let chains = chains.next();
+            let stop = stop.clone();
+            // Resumed renders continue with fresh streams rather than
+            // repeating the ones that produced the batches they start from
+            let rng = match config.seed {
+                Some(seed) => seeded_rng(seed, (number_batches as u64) << 32 | index as u64),
+                None => rand::weak_rng(),
+            };
+            workers.push(thread::spawn(move || {
+                worker(&tx, &config, chains, &stop, rng, share)
+            }));
+        }
+
+        Ok(RenderJob {
+            buffer: buffer,
+            number_batches: number_batches,
+            stats: Stats::new(config.channels.len()),
+            resumed_time: resumed_time,
+            resumed_batches: number_batches,
+            start_time: Instant::now(),
+            rx: rx,
+            stop: stop,
+            workers: workers,
+            config: config,
+        })
+    }
+
+    /// Merge the batches that are waiting, at most `max_count` at a time so
+    /// that a frontend stays responsive. Returns whether anything changed.
+    pub fn poll(&mut self, max_count: u32) -> bool {
+        let mut count = 0;
+        while let Ok(batch) = self.rx.try_recv() {
+            if !self.merge(batch) {
+                continue;
+            }
+            count += 1;
+            if count > max_count {
+                break;
+            }
+        }
+        count > 0
+    }
+
+    /// Block until `count` more batches have been merged, or the render is
+    /// finished.
+    pub fn run_batches(&mut self, count: u32) {
+        let target = self.number_batches.saturating_add(count);
+        while self.number_batches < target && !self.is_finished() {
+            match self.rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(batch) => {
+                    self.merge(batch);
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    /// Block merging batches until `deadline`, or until the render is finished.
+    pub fn run_until(&mut self, deadline: Instant) {
+        while !self.is_finished() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            match self.rx.recv_timeout(deadline - now) {
+                Ok(batch) => {
+                    self.merge(batch);
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    /// Add a batch to the total, returning whether it was used.
+    fn merge(&mut self, batch: Batch) -> bool {
+        // Batches that arrive after the budget is spent are dropped, so a
+        // render always has exactly `max_batches` batches
+        if self.config.max_batches.map_or(false, |max| self.number_batches >= max) {
+            return false;
+        }
+
+        for (target, &elem) in self.buffer.iter_mut().zip(batch.counts.iter()) {
+            *target += elem as u64;
+        }
+        self.stats.add(&batch.stats);
+        self.number_batches += 1;
+        true
+    }
+
+    /// Stop the workers and return their Metropolis chains.
+    pub fn finish(&mut self) -> Vec<Chains> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.workers
+            .drain(..)
+            .filter_map(|handle| handle.join().ok())
+            .collect()
+    }
+
+    /// Time spent rendering, including any runs this one resumed from.
+    pub fn elapsed(&self) -> Duration {
+        self.resumed_time + self.start_time.elapsed()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        if let Some(max_count) = self.config.max_batches {
+            if self.number_batches >= max_count {
+                return true;
+            }
+        }
+
+        if let Some(max_seconds) = self.config.max_seconds {
+            if self.start_time.elapsed() >= Duration::from_secs(max_seconds as u64) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn progress(&self) -> Progress {
+        fn secs(duration: Duration) -> f64 {
+            duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+        }
+
+        let stats = &self.stats;
+        let run_time = secs(self.start_time.elapsed());
+        let proposals: u64 = stats.proposals.iter().sum();
+        let run_batches = self.number_batches - self.resumed_batches;
+        let eta = self.config.max_batches.and_then(|max| {
+            if run_batches == 0 {
+                return None;
+            }
+            let left = max.saturating_sub(self.number_batches);
+            Some(run_time / run_batches as f64 * left as f64)
+        });
+
+        Progress {
+            batches: self.number_batches,
+            elapsed: secs(self.elapsed()),
+            samples_per_second: if run_time > 0.0 {
+                proposals as f64 / run_time
+            } else {
+                0.0
+            },
+            orbits: stats.orbits.iter().sum(),
+            eta: eta,
+            channels: (0..self.config.channels.len())
+                .map(|idx| {
+                    ChannelProgress {
+                        acceptance: stats.proposal_rate(&stats.accepted, idx),
+                        out_of_band: stats.proposal_rate(&stats.out_of_band, idx),
+                        out_of_view: stats.proposal_rate(&stats.out_of_view, idx),
+                        speedup: stats.speedup(idx),
+                        unseeded: stats.unseeded[idx],
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Tone-map the counts so far into a full resolution image.
+    pub fn to_image(&self) -> RgbImage {
+        to_image(
+            self.config.width,
+            self.config.height,
+            &self.config.channels,
+            &self.buffer,
+            &self.config.tone_map,
+        )
+    }
+
+    /// Save the image to `fname`, and the raw counts next to it if `save_raw`
+    /// is set. Does nothing without a `fname`.
+    pub fn save(&self) -> Result<(), RawError> {
+        let fname = match self.config.fname {
+            Some(ref fname) => fname,
+            None => return Ok(()),
+        };
+
+        save_image(
+            fname,
+            self.config.width,
+            self.config.height,
+            &self.config.channels,
+            &self.buffer,
+            &self.config.tone_map,
+        )?;
+
+        if self.config.save_raw {
+            // Seeded renders leave out the time taken, so that the same seed
+            // always writes the same file
+            let elapsed = match self.config.seed {
+                Some(_) => 0.0,
+                None => self.progress().elapsed,
+            };
+            let header = RawHeader {
+                config: self.config.clone(),
+                batches: self.number_batches,
+                elapsed: elapsed,
+            };
+            write_raw(&format!("{}.raw", fname), &header, &self.buffer)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RenderJob {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Run without any window until the render hits `max_batches`, runs out of
+/// time, or is interrupted. Each progress report is passed to `on_report`,
+/// or the error if it couldn't be logged. Fails if the log can't be opened.
+pub fn run_headless<F>(
+    job: &mut RenderJob,
+    interrupted: &AtomicBool,
+    mut on_report: F,
+) -> io::Result<()>
+where
+    F: FnMut(io::Result<Progress>),
+{
+    let mut reporter = Reporter::new(job)?;
+    while !job.is_finished() && !interrupted.load(Ordering::SeqCst) {
+        if !job.poll(10) {
+            thread::sleep(Duration::from_millis(50));
+        }
+        match reporter.tick(job) {
+            Ok(Some(progress)) => on_report(Ok(progress)),
+            Ok(None) => (),
+            Err(err) => on_report(Err(err)),
+        }
+    }
+    on_report(reporter.report(job));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    use config::from_toml;
+    use super::*;
+
+    #[test]
+    fn batch_budgets_are_split_evenly() {
+        assert_eq!(batch_shares(7, 3), vec![3, 2, 2]);
+        assert_eq!(batch_shares(8, 2), vec![4, 4]);
+        assert_eq!(batch_shares(2, 4), vec![1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn seeded_renders_save_the_same_raw_file() {
+        let fname = env::temp_dir().join("buddhabrot-test-seeded.png");
+        let fname = fname.to_string_lossy().into_owned();
+        let text = format!(
+            "width = 32\nheight = 32\nwindow_width = 32\nwindow_height = 32\n\
+             batch_steps = 200\nwarmup_count = 2\nn_threads = 3\nmax_batches = 7\n\
+             seed = 11\nred_limit = 200\ngreen_limit = 100\nblue_limit = 50\n\
+             save_raw = true\nfname = {:?}\n",
+            fname
+        );
+        let render = || {
+            let mut job = RenderJob::start(from_toml("test", &text).unwrap()).unwrap();
+            job.run_batches(u32::max_value());
+            // Every worker sent exactly its share, so none were dropped
+            assert_eq!(job.number_batches, 7);
+            assert!(job.rx.try_recv().is_err());
+            job.finish();
+            job.save().unwrap();
+
+            let mut bytes = Vec::new();
+            let raw = format!("{}.raw", fname);
+            File::open(&raw).unwrap().read_to_end(&mut bytes).unwrap();
+            fs::remove_file(&raw).unwrap();
+            let _ = fs::remove_file(&fname);
+            bytes
+        };
+
+        assert_eq!(render(), render());
+    }
+}
@@ -0,0 +1,523 @@
+use std::f64::consts::PI;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use complex::Complex;
+use buffer::Buffer;
+use config::{Channel, Config};
+use fractal::Fractal;
+use projection::Projection;
+use stats::{Batch, Stats};
+
+/// A worker's Metropolis chains: sets of one sample and its contribution per channel.
+pub type Chains = Vec<Vec<(Complex, f64)>>;
+
+/// The random stream for one worker of a render with `seed`. Different streams
+/// of the same seed are independent of each other.
+pub fn seeded_rng(seed: u64, stream: u64) -> XorShiftRng {
+    // SplitMix64, so that nearby seeds and streams give unrelated states
+    let mut state = seed ^ stream.wrapping_mul(0xD1B5_4A32_D192_ED03);
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    let (a, b) = (next(), next());
+    let mut words = [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32];
+    // XorShift can't start from an all zero state
+    if words == [0; 4] {
+        words[0] = 1;
+    }
+    XorShiftRng::from_seed(words)
+}
+
+/// Propose a new sample: one time in five a uniformly random point, otherwise
+/// a small step from `value` whose length is scaled to the view.
+fn mutate<R: Rng>(value: Complex, zoom: f64, fractal: &Fractal, rng: &mut R) -> Complex {
+    if rng.gen_weighted_bool(5) {
+        fractal.random_point(rng)
+    } else {
+        let r1 = 0.0001 / zoom;
+        let r2 = 0.1 / zoom;
+        let phi = rng.gen::<f64>() * 2.0 * PI;
+        let r = r2 * (-(r2 / r1).ln() * rng.gen::<f64>()).exp();
+
+        value + Complex::from_floats(r * phi.cos(), r * phi.sin())
+    }
+}
+
+/// The Metropolis acceptance probability, where the contribution of an orbit is
+/// the fraction of its points that land in view. For the anti-Buddhabrot every
+/// orbit runs for the full `length`, so the transition terms cancel and only
+/// the contributions matter.
+fn accept_prob(
+    length: u32,
+    current: &[Complex],
+    cur_contrib: f64,
+    proposed: &[Complex],
+    prop_contrib: f64,
+) -> f64 {
+
+    fn transition_prob(length: f64, from: &[Complex], to: &[Complex]) -> f64 {
+        (1.0 - (length - from.len() as f64) / length) / (1.0 - (length - to.len() as f64) / length)
+    }
+
+    // Tx = p(x' -> x)
+    let t0 = transition_prob(length as f64, proposed, current);
+    // Tx' = p(x -> x')
+    let t1 = transition_prob(length as f64, current, proposed);
+
+    // (Fx' * Tx') / (Fx * Tx)
+    ((cur_contrib * t0) / (prop_contrib * t1)).min(1.0)
+}
+
+fn find_initial_sample<R: Rng>(
+    buf: &Buffer,
+    channel: &Channel,
+    fractal: &Fractal,
+    origin: Complex,
+    rad: f64,
+    depth: u32,
+    stop: &AtomicBool,
+    rng: &mut R,
+) -> Option<Complex> {
+    if depth > 500 || stop.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let mut seed = Complex::default();
+
+    let mut closest = 1e20;
+    let mut orbit = Vec::with_capacity(channel.limit as usize);
+    for _ in 0..200 {
+        // The sampling disk shrunk down to `rad` around `origin`
+        let tmp = fractal.random_in_domain(rng) * (rad / fractal.sample_radius()) + origin;
+        if fractal.is_interior(tmp) {
+            continue;
+        }
+        if !fractal.accepts(channel, fractal.evaluate(tmp, channel.limit, &mut orbit)) {
+            continue;
+        }
+
+        let c = fractal.parameter(tmp);
+        let contrib = orbit.iter().filter(|&&x| buf.check(x, c)).count();
+        if contrib > 0 {
+            return Some(tmp);
+        }
+
+        for &point in &orbit {
+            let d = buf.offset(point, c).norm2();
+            if d < closest {
+                closest = d;
+                seed = tmp;
+            }
+        }
+    }
+
+    find_initial_sample(buf, channel, fractal, seed, rad / 2.0, depth + 1, stop, rng)
+}
+
+/// Build `n_samples` sets of Metropolis chains, with one chain per channel,
+/// counting the chains that no sample could be found for in `unseeded`.
+/// Gives up early once `stop` is set.
+fn build_initial_samples<R: Rng>(
+    buf: &Buffer,
+    channels: &[Channel],
+    fractal: &Fractal,
+    n_samples: u32,
+    unseeded: &mut [u64],
+    stop: &AtomicBool,
+    rng: &mut R,
+) -> Chains {
+    let mut output = Vec::with_capacity(n_samples as usize);
+    let mut orbit = Vec::new();
+    for _ in 0..n_samples {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let mut values = vec![(Complex::default(), 0.0); channels.len()];
+        for (idx, (value, channel)) in values.iter_mut().zip(channels).enumerate() {
+            let iterations = channel.limit;
+            let (origin, rad) = (Complex::default(), fractal.sample_radius());
+            match find_initial_sample(buf, channel, fractal, origin, rad, 0, stop, rng) {
+                Some(point) => {
+                    fractal.evaluate(point, iterations, &mut orbit);
+                    let c = fractal.parameter(point);
+                    let steps = orbit.iter().filter(|&&x| buf.check(x, c)).count();
+                    *value = (point, steps as f64 / iterations as f64);
+                }
+                None if stop.load(Ordering::Relaxed) => break,
+                None => unseeded[idx] += 1,
+            }
+        }
+        output.push(values);
+    }
+    output
+}
+
+/// Move chains that converged for a nearby view over to the view of `buf`,
+/// restarting any chain whose orbit no longer lands in view, and counting the
+/// ones that can't be restarted in `unseeded`.
+fn retarget_chains<R: Rng>(
+    buf: &Buffer,
+    channels: &[Channel],
+    fractal: &Fractal,
+    chains: &mut Chains,
+    unseeded: &mut [u64],
+    stop: &AtomicBool,
+    rng: &mut R,
+) {
+    let mut orbit = Vec::new();
+    let mapping = chains
+        .iter_mut()
+        .flat_map(|x| x.iter_mut().zip(channels.iter().enumerate()));
+    for (&mut (ref mut sample, ref mut contrib), (idx, channel)) in mapping {
+        if stop.load(Ordering::Relaxed) {
+            return;
+        }
+        let limit = channel.limit;
+        if fractal.accepts(channel, fractal.evaluate(*sample, limit, &mut orbit)) {
+            let c = fractal.parameter(*sample);
+            let count = orbit.iter().filter(|&&x| buf.check(x, c)).count();
+            if count > 0 {
+                *contrib = count as f64 / limit as f64;
+                continue;
+            }
+        }
+
+        let (origin, rad) = (Complex::default(), fractal.sample_radius());
+        let restart = find_initial_sample(buf, channel, fractal, origin, rad, 0, stop, rng);
+        match restart {
+            Some(point) => {
+                fractal.evaluate(point, limit, &mut orbit);
+                let c = fractal.parameter(point);
+                let count = orbit.iter().filter(|&&x| buf.check(x, c)).count();
+                *sample = point;
+                *contrib = count as f64 / limit as f64;
+            }
+            None if stop.load(Ordering::Relaxed) => return,
+            None => unseeded[idx] += 1,
+        }
+    }
+}
+
+/// Run each chain for a while so it settles into the parts of the plane that
+/// matter before anything is plotted, stopping early once `stop` is set.
+fn warmup<R: Rng>(
+    buf: &Buffer,
+    channels: &[Channel],
+    fractal: &Fractal,
+    samples: &mut [Vec<(Complex, f64)>],
+    stop: &AtomicBool,
+    rng: &mut R,
+) {
+    let mut current = Vec::new();
+    let mut proposed = Vec::new();
+    let mapping = samples.iter_mut().flat_map(|x| x.iter_mut().zip(channels));
+    for (&mut (ref mut c, ref mut contrib), channel) in mapping {
+        let limit = channel.limit;
+        for step in 0..10000 {
+            if step % 100 == 0 && stop.load(Ordering::Relaxed) {
+                return;
+            }
+            fractal.evaluate(*c, limit, &mut current);
+            let c2 = mutate(*c, buf.zoom, fractal, rng);
+            if fractal.is_interior(c2) {
+                continue;
+            }
+
+            if fractal.accepts(channel, fractal.evaluate(c2, limit, &mut proposed)) {
+                let c2_param = fractal.parameter(c2);
+                let count = proposed.iter().filter(|x| buf.check(**x, c2_param)).count();
+                if count == 0 {
+                    continue;
+                }
+                let proposed_contrib = count as f64 / limit as f64;
+
+                let alpha = accept_prob(limit, &current, *contrib, &proposed, proposed_contrib);
+                if rng.gen::<f64>() < alpha {
+                    *c = c2;
+                    *contrib = proposed_contrib;
+                }
+            }
+        }
+    }
+}
+
+/// Render batches for `config` until `stop` is set, the receiver hangs up, or
+/// `batches` batches are done, then return the Metropolis chains so that a
+/// nearby view can continue them. Without `chains`, new ones are found and
+/// warmed up from scratch. All randomness comes from `rng`.
+pub fn worker<R: Rng>(
+    tx: &Sender<Batch>,
+    config: &Config,
+    chains: Option<Chains>,
+    stop: &AtomicBool,
+    mut rng: R,
+    batches: Option<u32>,
+) -> Chains {
+    let channels = config.channels.len();
+    let fractal = Fractal::from_config(config);
+    let projection = Projection::new(&config.rotation);
+    let mut data = Buffer::new(
+        config.width,
+        config.height,
+        channels,
+        config.origin,
+        config.zoom,
+        projection,
+    );
+    let mut samples = vec![vec![(Complex::default(), 0.0); channels]];
+    // Reported with the first batch
+    let mut unseeded = vec![0; channels];
+
+    if config.use_metropolis {
+        match chains {
+            Some(chains) => {
+                samples = chains;
+                retarget_chains(
+                    &data,
+                    &config.channels,
+                    &fractal,
+                    &mut samples,
+                    &mut unseeded,
+                    stop,
+                    &mut rng,
+                );
+            }
+            None => {
+                samples = build_initial_samples(
+                    &data,
+                    &config.channels,
+                    &fractal,
+                    config.warmup_count,
+                    &mut unseeded,
+                    stop,
+                    &mut rng,
+                );
+                warmup(&data, &config.channels, &fractal, &mut samples, stop, &mut rng);
+            }
+        }
+    }
+
+    let max_limit = config.channels.iter().map(|x| x.limit).max().unwrap();
+    let mut current = Vec::with_capacity(max_limit as usize);
+    let mut proposed = Vec::with_capacity(max_limit as usize);
+
+    let mut done = 0;
+    while !stop.load(Ordering::Relaxed) && batches.map_or(true, |n| done < n) {
+        let mut stats = Stats::new(channels);
+        stats.unseeded = mem::replace(&mut unseeded, vec![0; channels]);
+        data = Buffer::new(
+            config.width,
+            config.height,
+            channels,
+            config.origin,
+            config.zoom,
+            projection,
+        );
+        for _ in 0..config.batch_steps {
+            if stop.load(Ordering::Relaxed) {
+                return samples;
+            }
+            let mapping = samples
+                .iter_mut()
+                .flat_map(|x| x.iter_mut().zip(config.channels.iter().enumerate()));
+            for (&mut (ref mut c, ref mut contrib), (i, channel)) in mapping {
+                let limit = channel.limit;
+                let escape_time = fractal.evaluate(*c, limit, &mut current);
+                stats.record_orbit(i, limit, escape_time, current.len());
+                let in_band = fractal.accepts(channel, escape_time);
+                let c2 = if config.use_metropolis {
+                    mutate(*c, data.zoom, &fractal, &mut rng)
+                } else {
+                    fractal.random_point(&mut rng)
+                };
+                stats.proposals[i] += 1;
+                if fractal.is_interior(c2) {
+                    stats.out_of_band[i] += 1;
+                    continue;
+                }
+
+                let escape_time = fractal.evaluate(c2, limit, &mut proposed);
+                stats.record_orbit(i, limit, escape_time, proposed.len());
+                if fractal.accepts(channel, escape_time) {
+                    let c2_param = fractal.parameter(c2);
+                    let count = proposed.iter().filter(|x| data.check(**x, c2_param)).count();
+                    if count == 0 {
+                        stats.out_of_view[i] += 1;
+                        continue;
+                    }
+                    let proposed_contrib = count as f64 / limit as f64;
+
+                    let alpha = accept_prob(limit, &current, *contrib, &proposed, proposed_contrib);
+                    if !config.use_metropolis || rng.gen::<f64>() < alpha {
+                        stats.accepted[i] += 1;
+                        // `current` is still the orbit of the old sample
+                        let param = fractal.parameter(*c);
+                        *c = c2;
+                        *contrib = proposed_contrib;
+                        if in_band {
+                            for &point in current.iter().skip(1) {
+                                data.increment(i, point, param);
+                            }
+                        }
+                    }
+                } else {
+                    stats.out_of_band[i] += 1;
+                }
+            }
+        }
+
+        let batch = Batch {
+            counts: data.buffer,
+            stats: stats,
+        };
+        match tx.send(batch) {
+            Ok(()) => done += 1,
+            Err(_) => break,
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc::channel;
+
+    use config::{Config, from_toml};
+    use super::*;
+
+    /// A config small enough to render in a moment.
+    fn small_config() -> Config {
+        let text = "width = 32\nheight = 32\nwindow_width = 32\nwindow_height = 32\n\
+                    batch_steps = 200\nwarmup_count = 2\nn_threads = 1\n\
+                    red_limit = 200\ngreen_limit = 100\nblue_limit = 50\n";
+        from_toml("test", text).unwrap()
+    }
+
+    fn fixed_rng() -> XorShiftRng {
+        XorShiftRng::from_seed([1, 2, 3, 4])
+    }
+
+    fn view_of(config: &Config) -> Buffer {
+        let projection = Projection::new(&config.rotation);
+        let channels = config.channels.len();
+        Buffer::new(config.width, config.height, channels, config.origin, config.zoom, projection)
+    }
+
+    /// Check that `sample` is in the band of `channel`, and that `contrib` is
+    /// the fraction of its orbit in view.
+    fn check_sample(buf: &Buffer, fractal: &Fractal, channel: &Channel, sample: Complex) -> f64 {
+        let mut orbit = Vec::new();
+        let escape_time = fractal.evaluate(sample, channel.limit, &mut orbit);
+        assert!(fractal.accepts(channel, escape_time));
+        let c = fractal.parameter(sample);
+        let count = orbit.iter().filter(|&&z| buf.check(z, c)).count();
+        assert!(count > 0);
+        count as f64 / channel.limit as f64
+    }
+
+    #[test]
+    fn mutate_is_repeatable_and_takes_small_steps() {
+        let fractal = Fractal::from_config(&small_config());
+        let start = Complex::from_floats(-0.5, 0.6);
+        let zoom = 10.0;
+        let steps = |mut rng: XorShiftRng| -> Vec<Complex> {
+            (0..200).map(|_| mutate(start, zoom, &fractal, &mut rng)).collect()
+        };
+
+        let points = steps(fixed_rng());
+        assert_eq!(points, steps(fixed_rng()));
+        let small = points
+            .iter()
+            .filter(|&&point| (point - start).norm2().sqrt() <= 0.1 / zoom)
+            .count();
+        assert!(small > 100);
+        // Everything else is a fresh point from the whole plane
+        for &point in &points {
+            assert!(point.norm2().sqrt() <= fractal.sample_radius());
+        }
+    }
+
+    #[test]
+    fn initial_samples_land_in_view() {
+        let config = small_config();
+        let fractal = Fractal::from_config(&config);
+        let buf = view_of(&config);
+        let stop = AtomicBool::new(false);
+        let origin = Complex::default();
+        for channel in &config.channels {
+            let find = |mut rng: XorShiftRng| {
+                find_initial_sample(&buf, channel, &fractal, origin, 2.0, 0, &stop, &mut rng)
+            };
+            let sample = find(fixed_rng()).unwrap();
+            assert_eq!(find(fixed_rng()), Some(sample));
+            check_sample(&buf, &fractal, channel, sample);
+        }
+    }
+
+    #[test]
+    fn initial_sample_search_gives_up_when_stopped() {
+        let config = small_config();
+        let fractal = Fractal::from_config(&config);
+        let buf = view_of(&config);
+        let stop = AtomicBool::new(true);
+        let channel = &config.channels[0];
+        let origin = Complex::default();
+        let mut rng = fixed_rng();
+        let sample = find_initial_sample(&buf, channel, &fractal, origin, 2.0, 0, &stop, &mut rng);
+        assert_eq!(sample, None);
+    }
+
+    #[test]
+    fn warmup_is_repeatable_and_keeps_chains_in_view() {
+        let config = small_config();
+        let fractal = Fractal::from_config(&config);
+        let buf = view_of(&config);
+        let stop = AtomicBool::new(false);
+        let warm = |mut rng: XorShiftRng| {
+            let mut unseeded = vec![0; config.channels.len()];
+            let channels = &config.channels;
+            let mut samples =
+                build_initial_samples(&buf, channels, &fractal, 2, &mut unseeded, &stop, &mut rng);
+            assert_eq!(unseeded, vec![0; channels.len()]);
+            warmup(&buf, channels, &fractal, &mut samples, &stop, &mut rng);
+            samples
+        };
+
+        let samples = warm(fixed_rng());
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples, warm(fixed_rng()));
+        for chain in &samples {
+            for (&(sample, contrib), channel) in chain.iter().zip(&config.channels) {
+                assert_eq!(check_sample(&buf, &fractal, channel, sample), contrib);
+            }
+        }
+    }
+
+    /// The counts of each batch that a worker renders.
+    fn render(config: &Config, seed: u64, stream: u64, batches: u32) -> Vec<Vec<u32>> {
+        let (tx, rx) = channel();
+        let stop = AtomicBool::new(false);
+        worker(&tx, config, None, &stop, seeded_rng(seed, stream), Some(batches));
+        drop(tx);
+        rx.iter().map(|batch| batch.counts.to_vec()).collect()
+    }
+
+    #[test]
+    fn seeded_worker_repeats_its_counts() {
+        let config = small_config();
+        let counts = render(&config, 7, 3, 2);
+        assert_eq!(counts.len(), 2);
+        assert!(counts.iter().all(|batch| batch.iter().any(|&x| x > 0)));
+        assert_eq!(counts, render(&config, 7, 3, 2));
+        assert!(counts != render(&config, 7, 4, 2));
+    }
+}
@@ -0,0 +1,94 @@
+/// Counters gathered by the workers, with one entry per channel.
+#[derive(Clone, Debug)]
+pub struct Stats {
+    /// Orbits evaluated, for both the current and the proposed samples
+    pub orbits: Vec<u64>,
+    /// Orbit iterations that were actually computed
+    pub iterations: Vec<u64>,
+    /// Iterations avoided by spotting a periodic orbit before its limit
+    pub skipped_iterations: Vec<u64>,
+    /// New samples proposed
+    pub proposals: Vec<u64>,
+    /// Proposals that the chain moved to
+    pub accepted: Vec<u64>,
+    /// Proposals rejected because their escape time is outside the channel's
+    /// band, such as orbits that never escape
+    pub out_of_band: Vec<u64>,
+    /// Proposals rejected because none of their orbit lands in view
+    pub out_of_view: Vec<u64>,
+    /// Chains that no starting sample in view could be found for
+    pub unseeded: Vec<u64>,
+}
+
+impl Stats {
+    pub fn new(channels: usize) -> Self {
+        Stats {
+            orbits: vec![0; channels],
+            iterations: vec![0; channels],
+            skipped_iterations: vec![0; channels],
+            proposals: vec![0; channels],
+            accepted: vec![0; channels],
+            out_of_band: vec![0; channels],
+            out_of_view: vec![0; channels],
+            unseeded: vec![0; channels],
+        }
+    }
+
+    /// Record an orbit of `channel` that was evaluated with `limit` iterations.
+    #[inline]
+    pub fn record_orbit(
+        &mut self,
+        channel: usize,
+        limit: u32,
+        escape_time: Option<u32>,
+        len: usize,
+    ) {
+        self.orbits[channel] += 1;
+        self.iterations[channel] += len as u64;
+        if escape_time.is_none() {
+            self.skipped_iterations[channel] += (limit as u64).saturating_sub(len as u64);
+        }
+    }
+
+    pub fn add(&mut self, other: &Stats) {
+        fn add_all(a: &mut [u64], b: &[u64]) {
+            for (a, b) in a.iter_mut().zip(b) {
+                *a += *b;
+            }
+        }
+
+        add_all(&mut self.orbits, &other.orbits);
+        add_all(&mut self.iterations, &other.iterations);
+        add_all(&mut self.skipped_iterations, &other.skipped_iterations);
+        add_all(&mut self.proposals, &other.proposals);
+        add_all(&mut self.accepted, &other.accepted);
+        add_all(&mut self.out_of_band, &other.out_of_band);
+        add_all(&mut self.out_of_view, &other.out_of_view);
+        add_all(&mut self.unseeded, &other.unseeded);
+    }
+
+    /// How many times more iterations `channel` would have needed without
+    /// periodicity checking.
+    pub fn speedup(&self, channel: usize) -> f64 {
+        let done = self.iterations[channel];
+        if done == 0 {
+            return 1.0;
+        }
+        (done + self.skipped_iterations[channel]) as f64 / done as f64
+    }
+
+    /// The fraction of the proposals for `channel` that were counted in `counts`.
+    pub fn proposal_rate(&self, counts: &[u64], channel: usize) -> f64 {
+        let proposals = self.proposals[channel];
+        if proposals == 0 {
+            return 0.0;
+        }
+        counts[channel] as f64 / proposals as f64
+    }
+}
+
+/// The counts and statistics from one batch of a worker.
+pub struct Batch {
+    pub counts: Box<[u32]>,
+    pub stats: Stats,
+}
@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+use std::slice::Iter;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::channel;
+use std::time::Instant;
+
+use rand::{self, Rng};
+
+use buddhabrot::color::save_image;
+use buddhabrot::config::{Config, DEFAULT_LOCATIONS, get_config, read_locations};
+use buddhabrot::raw::{read_raw, write_raw};
+use buddhabrot::sampler::{seeded_rng, worker};
+
+/// Parse the value that follows a `--flag` on the command line.
+fn flag_value<T: FromStr>(args: &mut Iter<String>, flag: &str) -> Result<T, String> {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("`{}` needs a numeric value", flag))
+}
+
+/// `retonemap <input.raw> <output.png> [--gain G] [--exposure E] [--white-point W]`
+///
+/// Produce a new image from a raw file without re-rendering it. Settings that
+/// aren't given fall back to the tone mapping stored with the raw file.
+pub fn retonemap(args: &[String]) -> Result<(), String> {
+    let mut paths = Vec::new();
+    let mut overrides = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--gain" | "--exposure" | "--white-point" => {
+                overrides.push((arg.clone(), flag_value::<f64>(&mut args, arg)?));
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option `{}`", arg)),
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 2 {
+        return Err(String::from(
+            "Usage: retonemap <input.raw> <output.png> \
+             [--gain G] [--exposure E] [--white-point W]",
+        ));
+    }
+
+    let raw = read_raw(paths[0]).map_err(|err| format!("Error reading '{}': {}", paths[0], err))?;
+    let config = &raw.header.config;
+
+    let mut tone_map = config.tone_map;
+    for (flag, value) in overrides {
+        match &flag[..] {
+            "--gain" => tone_map.gain = value,
+            "--exposure" => tone_map.exposure = value,
+            _ => tone_map.white_point = value,
+        }
+    }
+    if let Some((key, message)) = tone_map.errors().into_iter().next() {
+        return Err(format!("`--{}` {}", key.replace('_', "-"), message));
+    }
+
+    println!("Saving image...");
+    save_image(
+        paths[1],
+        config.width,
+        config.height,
+        &config.channels,
+        &raw.content,
+        &tone_map,
+    ).map_err(|err| format!("Error saving '{}': {}", paths[1], err))
+}
+
+/// `merge <output.raw> <input.raw>... [--png output.png]`
+///
+/// Sum the counts of several renders of the same view, such as the same config
+/// run on different machines.
+pub fn merge(args: &[String]) -> Result<(), String> {
+    let mut paths = Vec::new();
+    let mut png = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--png" => png = Some(args.next().ok_or("`--png` needs a file name")?),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option `{}`", arg)),
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() < 2 {
+        return Err(String::from(
+            "Usage: merge <output.raw> <input.raw>... [--png output.png]",
+        ));
+    }
+
+    let (output, inputs) = paths.split_first().unwrap();
+    let first = read_raw(inputs[0])
+        .map_err(|err| format!("Error reading '{}': {}", inputs[0], err))?;
+    let mut header = first.header;
+    let mut content = first.content;
+
+    let mut overflowed = 0;
+    for path in &inputs[1..] {
+        let raw = read_raw(path).map_err(|err| format!("Error reading '{}': {}", path, err))?;
+        raw.header
+            .check_matches(&header.config)
+            .map_err(|err| format!("Can't merge '{}': {}", path, err))?;
+
+        for (target, &elem) in content.iter_mut().zip(raw.content.iter()) {
+            *target = target.checked_add(elem).unwrap_or_else(|| {
+                overflowed += 1;
+                u64::max_value()
+            });
+        }
+        header.batches += raw.header.batches;
+        header.elapsed += raw.header.elapsed;
+    }
+
+    if overflowed > 0 {
+        println!(
+            "Warning: {} counts overflowed and were clamped to {}",
+            overflowed,
+            u64::max_value()
+        );
+    }
+
+    println!("Merged {} files with {} batches in total", inputs.len(), header.batches);
+    println!("Saving raw...");
+    write_raw(output, &header, &content)
+        .map_err(|err| format!("Error saving '{}': {}", output, err))?;
+
+    if let Some(png) = png {
+        println!("Saving image...");
+        let config = &header.config;
+        save_image(
+            png,
+            config.width,
+            config.height,
+            &config.channels,
+            &content,
+            &config.tone_map,
+        ).map_err(|err| format!("Error saving '{}': {}", png, err))?;
+    }
+
+    Ok(())
+}
+
+/// `bench [config.toml] [--batches N]`
+///
+/// Time the sampler for `config` on a single thread, once drawing its random
+/// numbers from the thread-local generator and once from the per-worker
+/// generator that renders use.
+pub fn bench(args: &[String]) -> Result<(), String> {
+    let mut batches = 4;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--batches" => batches = flag_value(&mut args, arg)?,
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    let config = get_config(&rest).map_err(|err| err.to_string())?;
+    let seed = config.seed.unwrap_or(1);
+    println!("Timing {} batches of {} steps per generator...", batches, config.batch_steps);
+    let slow = time_worker(&config, batches, rand::thread_rng());
+    let fast = time_worker(&config, batches, seeded_rng(seed, 0));
+    println!("thread_rng: {:.0} iterations/s", slow);
+    println!("XorShift:   {:.0} iterations/s ({:.2}x)", fast, fast / slow);
+    Ok(())
+}
+
+/// Orbit iterations per second of one worker rendering `batches` batches,
+/// including the time it spends warming up.
+fn time_worker<R: Rng>(config: &Config, batches: u32, rng: R) -> f64 {
+    let (tx, rx) = channel();
+    let stop = AtomicBool::new(false);
+    let start = Instant::now();
+    worker(&tx, config, None, &stop, rng, Some(batches));
+    let elapsed = start.elapsed();
+
+    let iterations: u64 = rx.try_iter()
+        .map(|batch| batch.stats.iterations.iter().sum::<u64>())
+        .sum();
+    iterations as f64 / (elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9)
+}
+
+/// `locations [locations.toml]`
+///
+/// List the named views in a locations file, by default the one next to the
+/// example configs.
+pub fn locations(args: &[String]) -> Result<(), String> {
+    let path = match args.len() {
+        0 => Path::new("examples").join(DEFAULT_LOCATIONS),
+        1 => PathBuf::from(&args[0]),
+        _ => return Err(String::from("Usage: locations [locations.toml]")),
+    };
+    let locations = read_locations(&path.to_string_lossy()).map_err(|err| err.to_string())?;
+
+    let width = locations.iter().map(|x| x.name.len()).max().unwrap_or(0);
+    for location in &locations {
+        let mut line = format!(
+            "{:width$}  {} {:+}i  zoom {}",
+            location.name,
+            location.origin.r,
+            location.origin.i,
+            location.zoom,
+            width = width
+        );
+        if let Some(ref limits) = location.limits {
+            let limits: Vec<String> = limits.iter().map(|x| x.to_string()).collect();
+            line.push_str(&format!("  limits {}", limits.join("/")));
+        }
+        println!("{}", line);
+        if let Some(ref notes) = location.notes {
+            println!("{:width$}  {}", "", notes, width = width);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,315 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use sdl2;
+use sdl2::event::Event;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, Renderer};
+
+use buddhabrot::color::{ToneMap, color_map_buffer};
+use buddhabrot::complex::Complex;
+use buddhabrot::config::{Channel, Config};
+use buddhabrot::progress::Reporter;
+use buddhabrot::render::RenderJob;
+
+/// How long to wait after the last scroll of the mouse wheel before restarting the render.
+const WHEEL_DELAY_MS: u64 = 300;
+/// How much one click of the mouse wheel zooms by.
+const WHEEL_ZOOM: f64 = 1.25;
+
+/// The part of the plane shown in the window.
+#[derive(Clone, Copy, PartialEq)]
+struct View {
+    origin: Complex,
+    zoom: f64,
+}
+
+impl View {
+    fn of(config: &Config) -> Self {
+        View {
+            origin: config.origin,
+            zoom: config.zoom,
+        }
+    }
+
+    /// The point shown at window position `(x, y)`, the inverse of `Buffer::project`.
+    fn plane_point(&self, config: &Config, (x, y): (f64, f64)) -> Complex {
+        let size = config.width.min(config.height) as f64;
+        let aspect = config.width as f64 / config.height as f64;
+        let x = x * config.width as f64 / config.window_width as f64;
+        let y = y * config.height as f64 / config.window_height as f64;
+        let offset = Complex::from_floats(
+            (x / size - 0.5 * aspect) / self.zoom,
+            (y / size - 0.5) / self.zoom,
+        );
+        self.origin + offset
+    }
+
+    /// The window position that shows `point`.
+    fn window_point(&self, config: &Config, point: Complex) -> (f64, f64) {
+        let size = config.width.min(config.height) as f64;
+        let aspect = config.width as f64 / config.height as f64;
+        let offset = point - self.origin;
+        let x = (offset.r * self.zoom + 0.5 * aspect) * size;
+        let y = (offset.i * self.zoom + 0.5) * size;
+        (
+            x * config.window_width as f64 / config.width as f64,
+            y * config.window_height as f64 / config.height as f64,
+        )
+    }
+
+    /// Zoom in by `factor`, keeping the point under window position `at` in place.
+    fn zoom_about(&mut self, config: &Config, at: (f64, f64), factor: f64) {
+        let point = self.plane_point(config, at);
+        self.origin = point + (self.origin - point) * (1.0 / factor);
+        self.zoom *= factor;
+    }
+}
+
+/// A mouse drag in progress, in window coordinates.
+enum Drag {
+    /// Dragging the image around with the left button, from the last position seen
+    Pan((i32, i32)),
+    /// Drawing a rectangle to zoom into with the right button
+    Zoom((i32, i32), (i32, i32)),
+}
+
+fn update_texture(
+    (width, height): (u32, u32),
+    (window_width, window_height): (u32, u32),
+    texture: &mut Texture,
+    channels: &[Channel],
+    buffer: &[u64],
+    display_buffer: &mut [u8],
+    tone_map: &ToneMap,
+) {
+    color_map_buffer(
+        width,
+        height,
+        window_width,
+        window_height,
+        channels,
+        buffer,
+        display_buffer,
+        tone_map,
+    );
+
+    texture
+        .update(None, display_buffer, window_width as usize * 3)
+        .unwrap();
+    texture.set_blend_mode(sdl2::render::BlendMode::Blend);
+    texture.set_alpha_mod(255);
+}
+
+/// Draw the texture, which shows `shown`, where it lands in `view`, along
+/// with the rectangle being dragged out if there is one.
+fn draw(
+    renderer: &mut Renderer,
+    texture: &Texture,
+    config: &Config,
+    shown: &View,
+    view: &View,
+    drag: &Option<Drag>,
+) {
+    renderer.set_draw_color(Color::RGB(0, 0, 0));
+    renderer.clear();
+
+    // Past this the old image is too blurry to be worth showing
+    let scale = view.zoom / shown.zoom;
+    if scale < 64.0 {
+        let corner = shown.plane_point(config, (0.0, 0.0));
+        let (x, y) = view.window_point(config, corner);
+        let dst = Rect::new(
+            x.round() as i32,
+            y.round() as i32,
+            (config.window_width as f64 * scale).round() as u32,
+            (config.window_height as f64 * scale).round() as u32,
+        );
+        renderer.copy(texture, None, Some(dst)).unwrap();
+    }
+
+    if let Some(Drag::Zoom((x0, y0), (x1, y1))) = *drag {
+        renderer.set_draw_color(Color::RGB(255, 255, 255));
+        let rect = Rect::new(
+            x0.min(x1),
+            y0.min(y1),
+            (x0.max(x1) - x0.min(x1)) as u32,
+            (y0.max(y1) - y0.min(y1)) as u32,
+        );
+        renderer.draw_rect(rect).unwrap();
+    }
+
+    renderer.present();
+}
+
+/// Fit the rectangle between window positions `a` and `b` into the window.
+/// Returns `None` for rectangles too small to have been meant.
+fn zoom_to_rect(config: &Config, view: &View, a: (i32, i32), b: (i32, i32)) -> Option<View> {
+    let (dx, dy) = ((b.0 - a.0).abs(), (b.1 - a.1).abs());
+    if dx < 4 || dy < 4 {
+        return None;
+    }
+
+    let centre = ((a.0 + b.0) as f64 / 2.0, (a.1 + b.1) as f64 / 2.0);
+    let factor = (config.window_width as f64 / dx as f64)
+        .min(config.window_height as f64 / dy as f64);
+    Some(View {
+        origin: view.plane_point(config, centre),
+        zoom: view.zoom * factor,
+    })
+}
+
+/// Stop `job` and start over from fresh chains showing `view`.
+fn restart(job: &mut RenderJob, view: &View) {
+    job.finish();
+    let mut config = job.config.clone();
+    config.origin = view.origin;
+    config.zoom = view.zoom;
+    // The counts from a raw file only fit the view they were rendered at
+    config.resume_from = None;
+    println!(
+        "Rendering r = {}, i = {}, zoom = {}",
+        config.origin.r,
+        config.origin.i,
+        config.zoom
+    );
+    *job = RenderJob::start(config).unwrap();
+}
+
+/// Show the render in an SDL window until it finishes or the window is closed.
+///
+/// Scrolling zooms about the mouse, dragging with the left button pans, and
+/// dragging with the right button zooms into a rectangle. Each change restarts
+/// the render at the new view.
+pub fn run(job: &mut RenderJob, interrupted: &AtomicBool) {
+    let config = job.config.clone();
+    let ctx = sdl2::init().unwrap();
+    let video_ctx = ctx.video().unwrap();
+    let mut event_pump = ctx.event_pump().unwrap();
+
+    let window = video_ctx
+        .window("Warming Up...", config.window_width, config.window_height)
+        .position_centered()
+        .allow_highdpi()
+        .opengl()
+        .build()
+        .unwrap();
+
+    let mut renderer: Renderer = window.renderer().build().unwrap();
+    // Keep drawing and mouse positions in window coordinates on high-DPI displays
+    renderer
+        .set_logical_size(config.window_width, config.window_height)
+        .unwrap();
+
+    let mut texture: Texture = renderer
+        .create_texture_streaming(
+            sdl2::pixels::PixelFormatEnum::RGB24,
+            config.window_width,
+            config.window_height,
+        )
+        .unwrap();
+
+    let mut display_buffer = vec![0_u8; (config.window_width * config.window_height) as usize * 3];
+    let mut reporter = match Reporter::new(job) {
+        Ok(reporter) => reporter,
+        Err(err) => {
+            println!("Error opening the progress log: {}", err);
+            return;
+        }
+    };
+
+    // The view the texture shows, and the view the user has asked for
+    let mut shown = View::of(&config);
+    let mut view = shown;
+    let mut drag = None;
+    let mut mouse = (0, 0);
+    let mut restart_at = None;
+    'all: loop {
+        let changed = job.poll(10);
+        match reporter.tick(job) {
+            Ok(Some(progress)) => println!("{}", progress),
+            Ok(None) => (),
+            Err(err) => println!("Error writing the progress log, no longer logging: {}", err),
+        }
+
+        if job.is_finished() || interrupted.load(Ordering::SeqCst) {
+            break 'all;
+        }
+
+        if changed {
+            update_texture(
+                (config.width, config.height),
+                (config.window_width, config.window_height),
+                &mut texture,
+                &config.channels,
+                &job.buffer,
+                &mut display_buffer,
+                &config.tone_map,
+            );
+            shown = View::of(&job.config);
+            renderer
+                .window_mut()
+                .unwrap()
+                .set_title(&job.progress().to_string())
+                .unwrap();
+        }
+
+        let mut moved = false;
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => break 'all,
+                Event::MouseMotion { x, y, .. } => {
+                    mouse = (x, y);
+                    match drag {
+                        Some(Drag::Pan(ref mut last)) => {
+                            let from = view.plane_point(&config, (last.0 as f64, last.1 as f64));
+                            let to = view.plane_point(&config, (x as f64, y as f64));
+                            view.origin = view.origin + from - to;
+                            *last = (x, y);
+                        }
+                        Some(Drag::Zoom(_, ref mut to)) => *to = (x, y),
+                        None => continue,
+                    }
+                    moved = true;
+                }
+                Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+                    drag = match mouse_btn {
+                        MouseButton::Left => Some(Drag::Pan((x, y))),
+                        MouseButton::Right => Some(Drag::Zoom((x, y), (x, y))),
+                        _ => continue,
+                    };
+                }
+                Event::MouseButtonUp { .. } => {
+                    if let Some(Drag::Zoom(a, b)) = drag.take() {
+                        if let Some(new_view) = zoom_to_rect(&config, &view, a, b) {
+                            view = new_view;
+                        }
+                    }
+                    restart_at = Some(Instant::now());
+                    moved = true;
+                }
+                Event::MouseWheel { y, .. } => {
+                    let at = (mouse.0 as f64, mouse.1 as f64);
+                    view.zoom_about(&config, at, WHEEL_ZOOM.powi(y));
+                    restart_at = Some(Instant::now() + Duration::from_millis(WHEEL_DELAY_MS));
+                    moved = true;
+                }
+                _ => {}
+            }
+        }
+
+        let due = restart_at.map_or(false, |at| Instant::now() >= at);
+        if due && drag.is_none() {
+            restart_at = None;
+            if view != View::of(&job.config) {
+                restart(job, &view);
+            }
+        }
+
+        if changed || moved {
+            draw(&mut renderer, &texture, &config, &shown, &view, &drag);
+        }
+    }
+}